@@ -3,8 +3,11 @@ fn main() {
         .out_dir("src/proto/build")
         .compile_protos(&[
             "src/proto/clustered_data_labels.proto",
+            "src/proto/expected_hand_strengths.proto",
+            "src/proto/hand_potentials.proto",
             "src/proto/hand_strength_histograms.proto",
-            "src/proto/opponent_cluster_hand_strength_histograms.proto"
+            "src/proto/opponent_cluster_hand_strength_histograms.proto",
+            "src/proto/potential_aware_histograms.proto"
             ], &["src/"])
         .unwrap();
 }
@@ -0,0 +1,33 @@
+use std::fs;
+
+use crate::encode::encode_cards;
+use crate::game_def::GameDef;
+use crate::hand_source::{EnumeratedHandSource, HandSource};
+use crate::canonical_hands::save::save_canonical_hands_to_file;
+
+// Enumerates every canonical hand of a round in index order and writes them in the
+// `round_{r}_batch_{b}.bin` format that `HandLoader` reads
+pub fn generate_canonical_hands(round: usize, batch_size: usize, game_def: GameDef, export_path: &str) {
+    fs::create_dir_all(export_path).expect("Failed to create canonical hands export folder");
+
+    let mut hand_source = EnumeratedHandSource::new(round, batch_size, game_def);
+    log::info!(
+        "Generating {} canonical hands for round {} in {} batches",
+        hand_source.total_hands,
+        round,
+        hand_source.total_batches
+    );
+
+    for batch_index in 0..hand_source.total_batches() {
+        let encoded_hands: Vec<i64> = hand_source.current_batch_hands().iter()
+            .map(|hand| encode_cards(hand))
+            .collect();
+
+        save_canonical_hands_to_file(encoded_hands, round, batch_index, export_path)
+            .expect(format!("ERROR: Failed to save canonical hands for round {} batch #{}", round, batch_index).as_str());
+
+        log::info!("Saved canonical hands. Round {}, batch {}/{}", round, batch_index, hand_source.total_batches()-1);
+
+        hand_source.load_next_batch();
+    }
+}
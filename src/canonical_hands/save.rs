@@ -0,0 +1,11 @@
+use std::fs::File;
+use std::io::BufWriter;
+use bincode;
+
+pub fn save_canonical_hands_to_file(encoded_hands: Vec<i64>, round: usize, batch: usize, export_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let filepath = format!("{}/round_{}_batch_{}.bin", export_path, round, batch);
+
+    let file = BufWriter::new(File::create(filepath)?);
+    bincode::serialize_into(file, &encoded_hands)?;
+    Ok(())
+}
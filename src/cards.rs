@@ -0,0 +1,294 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use itertools::Itertools;
+
+use crate::encode::{checked_decode_cards, encode_cards};
+use crate::game_def::GameDef;
+
+// A card is `rank << 2 | suit`, ranks from 2 up to A and suits in the order s, h, d, c
+static RANKS: &str = "23456789TJQKA";
+static SUITS: &str = "shdc";
+
+pub fn card_to_string(card: u8) -> String {
+    let rank = RANKS.as_bytes()[(card >> 2) as usize] as char;
+    let suit = SUITS.as_bytes()[(card & 3) as usize] as char;
+    format!("{}{}", rank, suit)
+}
+
+pub fn card_from_string(card: &str) -> Result<u8, Box<dyn Error>> {
+    let mut chars = card.chars();
+    let (Some(rank), Some(suit), None) = (chars.next(), chars.next(), chars.next()) else {
+        return Err(format!("Invalid card '{}'", card).into());
+    };
+    let rank = RANKS.find(rank.to_ascii_uppercase()).ok_or(format!("Invalid rank in card '{}'", card))?;
+    let suit = SUITS.find(suit.to_ascii_lowercase()).ok_or(format!("Invalid suit in card '{}'", card))?;
+    Ok((rank << 2 | suit) as u8)
+}
+
+// Parses a run of cards like "7c8c9c", whitespace and '|' separators are ignored
+pub fn cards_from_string(cards: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let chars: Vec<char> = cards.chars()
+        .filter(|char| !char.is_whitespace() && *char != '|')
+        .collect();
+    if chars.len() % 2 != 0 {
+        return Err(format!("Invalid cards '{}'", cards).into());
+    }
+
+    chars.chunks(2)
+        .map(|card| card_from_string(&card.iter().collect::<String>()))
+        .collect()
+}
+
+pub fn cards_to_string(cards: &[u8]) -> String {
+    cards.iter().map(|&card| card_to_string(card)).collect()
+}
+
+fn check_distinct(cards: &[Card]) -> Result<(), Box<dyn Error>> {
+    for (i, card) in cards.iter().enumerate() {
+        if cards[i + 1..].contains(card) {
+            return Err(format!("Card {} appears more than once", card).into());
+        }
+    }
+    Ok(())
+}
+
+// A single card of the 52-card deck, stored like everywhere else as `rank << 2 | suit`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Card(u8);
+
+impl Card {
+    pub fn new(card: u8) -> Result<Self, Box<dyn Error>> {
+        if card >= 52 {
+            return Err(format!("Invalid card value {}", card).into());
+        }
+        Ok(Self(card))
+    }
+
+    // 0 is a 2, 12 is an ace
+    pub fn rank(self) -> u8 {
+        self.0 >> 2
+    }
+
+    // In the order s, h, d, c
+    pub fn suit(self) -> u8 {
+        self.0 & 3
+    }
+
+    pub fn value(self) -> u8 {
+        self.0
+    }
+}
+
+impl FromStr for Card {
+    type Err = Box<dyn Error>;
+
+    fn from_str(card: &str) -> Result<Self, Self::Err> {
+        card_from_string(card).map(Self)
+    }
+}
+
+impl fmt::Display for Card {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", card_to_string(self.0))
+    }
+}
+
+impl From<Card> for u8 {
+    fn from(card: Card) -> u8 {
+        card.0
+    }
+}
+
+fn parse_distinct_cards(cards: &str) -> Result<Vec<Card>, Box<dyn Error>> {
+    let cards = cards_from_string(cards)?.into_iter().map(Card).collect_vec();
+    check_distinct(&cards)?;
+    Ok(cards)
+}
+
+// A player's private cards: 1 in Leduc and Kuhn, 2 in hold'em and 4 in Omaha
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct HoleCards(Vec<Card>);
+
+impl HoleCards {
+    pub fn new(cards: Vec<Card>) -> Result<Self, Box<dyn Error>> {
+        if cards.is_empty() || cards.len() > 4 {
+            return Err(format!("A player holds 1 to 4 cards, not {}", cards.len()).into());
+        }
+        check_distinct(&cards)?;
+        Ok(Self(cards))
+    }
+
+    pub fn cards(&self) -> &[Card] {
+        &self.0
+    }
+}
+
+impl FromStr for HoleCards {
+    type Err = Box<dyn Error>;
+
+    fn from_str(cards: &str) -> Result<Self, Self::Err> {
+        Self::new(parse_distinct_cards(cards)?)
+    }
+}
+
+impl fmt::Display for HoleCards {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.iter().try_for_each(|card| write!(f, "{}", card))
+    }
+}
+
+// The known community cards, empty preflop
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Board(Vec<Card>);
+
+impl Board {
+    pub fn new(cards: Vec<Card>) -> Result<Self, Box<dyn Error>> {
+        if cards.len() > 5 {
+            return Err(format!("A board has at most 5 cards, not {}", cards.len()).into());
+        }
+        check_distinct(&cards)?;
+        Ok(Self(cards))
+    }
+
+    pub fn cards(&self) -> &[Card] {
+        &self.0
+    }
+}
+
+impl FromStr for Board {
+    type Err = Box<dyn Error>;
+
+    fn from_str(cards: &str) -> Result<Self, Self::Err> {
+        Self::new(parse_distinct_cards(cards)?)
+    }
+}
+
+impl fmt::Display for Board {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.iter().try_for_each(|card| write!(f, "{}", card))
+    }
+}
+
+// Hole cards and board of one round, written like "AsKd|7c8c9c". As `Vec<u8>` (see `to_cards`)
+// it's the hole cards followed by the board, the layout the hand sources and generators use
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Hand {
+    pub hole_cards: HoleCards,
+    pub board: Board,
+}
+
+impl Hand {
+    pub fn new(hole_cards: HoleCards, board: Board) -> Result<Self, Box<dyn Error>> {
+        check_distinct(&[hole_cards.cards(), board.cards()].concat())?;
+        Ok(Self { hole_cards, board })
+    }
+
+    // Splits `cards` after the hole cards of `game_def` and checks that every card is in its deck
+    // and that the hand belongs to one of its rounds
+    pub fn from_cards(cards: &[u8], game_def: GameDef) -> Result<Self, Box<dyn Error>> {
+        if game_def.round_from_hand_size(cards.len()).is_none() {
+            return Err(format!("Hands of {} cards don't belong to any round of {:?}", cards.len(), game_def).into());
+        }
+        if let Some(&card) = cards.iter().find(|&&card| !game_def.contains_card(card)) {
+            // Values from 52 up aren't cards at all, so they can't be printed as one
+            let card = Card::new(card).map(|card| card.to_string()).unwrap_or_else(|_| format!("value {}", card));
+            return Err(format!("Card {} is not in the {:?} deck", card, game_def).into());
+        }
+
+        let cards = cards.iter().map(|&card| Card::new(card)).collect::<Result<Vec<_>, _>>()?;
+        let (hole_cards, board) = cards.split_at(game_def.hole_cards);
+        Self::new(HoleCards::new(hole_cards.to_vec())?, Board::new(board.to_vec())?)
+    }
+
+    // Parses "AsKd|7c8c9c" as well as "AsKd 7c8c9c" or "AsKd7c8c9c", the hole cards are the first
+    // `game_def.hole_cards` cards
+    pub fn parse(hand: &str, game_def: GameDef) -> Result<Self, Box<dyn Error>> {
+        Self::from_cards(&cards_from_string(hand)?, game_def)
+    }
+
+    pub fn to_cards(&self) -> Vec<u8> {
+        self.hole_cards.cards().iter().chain(self.board.cards()).map(|&card| card.value()).collect()
+    }
+
+    // Same encoding as the canonical hand batches
+    pub fn encode(&self) -> i64 {
+        encode_cards(&self.to_cards())
+    }
+
+    pub fn decode(encoded_cards: i64, game_def: GameDef) -> Result<Self, Box<dyn Error>> {
+        let cards = checked_decode_cards(encoded_cards).ok_or_else(|| format!("{} is not an encoded hand", encoded_cards))?;
+        Self::from_cards(&cards, game_def)
+    }
+}
+
+impl fmt::Display for Hand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.board.cards().is_empty() {
+            write!(f, "{}", self.hole_cards)
+        } else {
+            write!(f, "{}|{}", self.hole_cards, self.board)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::seq::SliceRandom;
+
+    use super::*;
+    use crate::encode::decode_cards;
+
+    #[test]
+    fn cards_print_as_they_parse() {
+        for card in 0..52 {
+            let card = Card::new(card).unwrap();
+            assert_eq!(card.to_string().parse::<Card>().unwrap(), card);
+        }
+        assert_eq!("As".parse::<Card>().unwrap(), Card::new(12 << 2).unwrap());
+        assert_eq!("2c".parse::<Card>().unwrap().suit(), 3);
+        assert!("1s".parse::<Card>().is_err());
+        assert!("Ax".parse::<Card>().is_err());
+        assert!(Card::new(52).is_err());
+    }
+
+    #[test]
+    fn hands_print_as_they_parse() {
+        let hand = Hand::parse("AsKd | 7c8c9c", GameDef::holdem()).unwrap();
+        assert_eq!(hand.to_string(), "AsKd|7c8c9c");
+        assert_eq!(Hand::parse(&hand.to_string(), GameDef::holdem()).unwrap(), hand);
+        assert_eq!(Hand::parse("AsKd", GameDef::holdem()).unwrap().to_string(), "AsKd");
+        assert_eq!("AsKd".parse::<HoleCards>().unwrap().to_string(), "AsKd");
+        assert_eq!("7c8c9c".parse::<Board>().unwrap().to_string(), "7c8c9c");
+    }
+
+    #[test]
+    fn duplicate_and_invalid_hands_are_rejected() {
+        assert!("AsAs".parse::<HoleCards>().is_err());
+        assert!("7c8c7c".parse::<Board>().is_err());
+        assert!(Hand::new("AsKd".parse().unwrap(), "AsQhJh".parse().unwrap()).is_err());
+        assert!(Hand::parse("AsKd7c", GameDef::holdem()).is_err());
+        assert!(Hand::parse("AsKd2c3c4c", GameDef::short_deck()).is_err());
+        assert!("2s3s4s5s6s7s".parse::<Board>().is_err());
+        assert!(Hand::from_cards(&[60, 1], GameDef::holdem()).is_err());
+        // 53 is the digits 1 and 0, and a zero digit doesn't encode any card
+        assert!(Hand::decode(53, GameDef::holdem()).is_err());
+        assert!(Hand::decode(-1, GameDef::holdem()).is_err());
+    }
+
+    #[test]
+    fn hands_round_trip_through_the_encoding() {
+        let game_def = GameDef::holdem();
+        let mut rng = rand::thread_rng();
+        let mut deck = game_def.deck_cards();
+        for _ in 0..1000 {
+            deck.shuffle(&mut rng);
+            for hand_size in (0..game_def.rounds()).map(|round| game_def.cards_in_round(round)) {
+                let hand = Hand::from_cards(&deck[..hand_size], game_def).unwrap();
+                assert_eq!(hand.to_cards(), deck[..hand_size]);
+                assert_eq!(Hand::decode(hand.encode(), game_def).unwrap(), hand);
+                assert_eq!(decode_cards(hand.encode()), hand.to_cards());
+            }
+        }
+    }
+}
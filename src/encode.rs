@@ -10,6 +10,22 @@ pub fn encode_cards(cards: &Vec<u8>) -> i64 {
     return encoded_cards;
 }
 
+// Like `decode_cards`, but `None` for values that no list of cards encodes to: negative values
+// and values with a zero digit
+pub fn checked_decode_cards(encoded_cards: i64) -> Option<Vec<u8>> {
+    if encoded_cards < 0 {
+        return None;
+    }
+    let mut cards: Vec<u8> = vec![];
+    let mut encoded_value = encoded_cards;
+    while encoded_value > 0 {
+        cards.push(((encoded_value % BASE_CARD) as u8).checked_sub(1)?);
+        encoded_value /= BASE_CARD;
+    }
+    cards.reverse();
+    Some(cards)
+}
+
 pub fn decode_cards(encoded_cards: i64) -> Vec<u8> {
     let mut cards: Vec<u8> = vec![];
     let mut encoded_value = encoded_cards;
@@ -0,0 +1,202 @@
+// CPU port of `evaluate_hand` in the OpenCL kernels. Scores compare the same way as the
+// kernel scores: a higher score is a better hand. Works for 5 up to 7 cards, or the 1 or 2
+// cards of Leduc and Kuhn hands.
+
+use itertools::Itertools;
+
+use crate::game_def::Evaluator;
+
+const STRAIGHT_FLUSH: u32 = 8;
+const FOUR_OF_A_KIND: u32 = 7;
+const STRAIGHT: u32 = 4;
+const THREE_OF_A_KIND: u32 = 3;
+const TWO_PAIR: u32 = 2;
+const ONE_PAIR: u32 = 1;
+const HIGH_CARD: u32 = 0;
+
+fn get_rank(card: u8) -> usize {
+    (card >> 2) as usize
+}
+
+// The kernel remaps suits to the order of the library the evaluation was copied from,
+// which only matters for telling suits apart, so the raw suit is used here
+fn get_suit(card: u8) -> usize {
+    (card & 3) as usize
+}
+
+// Keep only the most significant bit
+fn keep_highest(rank: u32) -> u32 {
+    if rank == 0 {
+        return 0;
+    }
+    1 << (31 - rank.leading_zeros())
+}
+
+// Keeping the N highest bits
+fn keep_n(mut rank: u32, to_keep: u32) -> u32 {
+    while rank.count_ones() > to_keep {
+        rank &= rank - 1; // Remove the least significant bit
+    }
+    rank
+}
+
+fn rank_hand(hand_type: u32, card_details: u32) -> u32 {
+    (hand_type << 27) | card_details
+}
+
+// A flush beats a full house in short deck
+fn full_house_and_flush(evaluator: Evaluator) -> (u32, u32) {
+    match evaluator {
+        Evaluator::Holdem | Evaluator::Omaha | Evaluator::Leduc => (6, 5),
+        Evaluator::ShortDeck => (5, 6),
+    }
+}
+
+// Identifying and ranking a straight in a set of card values
+fn rank_straight(value_set: u32, evaluator: Evaluator) -> u32 {
+    let left = value_set & (value_set << 1) & (value_set << 2) & (value_set << 3) & (value_set << 4);
+    if left != 0 {
+        let highest_card_rank = 32 - 4 - left.leading_zeros(); // Highest card in the straight
+        return (STRAIGHT << 27) | (1 << highest_card_rank);
+    }
+    match evaluator {
+        Evaluator::Holdem | Evaluator::Omaha | Evaluator::Leduc if (value_set & 0b1000000001111) == 0b1000000001111 => { // Wheel (A-2-3-4-5)
+            (STRAIGHT << 27) | (1 << 3)
+        }
+        Evaluator::ShortDeck if (value_set & 0b1000011110000) == 0b1000011110000 => { // Wheel (A-6-7-8-9)
+            (STRAIGHT << 27) | (1 << 4)
+        }
+        _ => 0 // No straight, zero is a valid return since no hand type uses it
+    }
+}
+
+fn find_flush(suit_value_sets: &[u32; 4]) -> Option<usize> {
+    suit_value_sets.iter().position(|suit_value_set| suit_value_set.count_ones() >= 5)
+}
+
+// Leduc and Kuhn hands: the private card followed by the board card, if any
+fn evaluate_leduc_hand(hand: &[u8]) -> u32 {
+    let private_rank = get_rank(hand[0]) as u32;
+    if hand[1..].iter().any(|&card| get_rank(card) == get_rank(hand[0])) {
+        return rank_hand(ONE_PAIR, private_rank);
+    }
+    rank_hand(HIGH_CARD, private_rank)
+}
+
+pub fn evaluate_hand(hand: &[u8], evaluator: Evaluator) -> u32 {
+    if evaluator == Evaluator::Leduc {
+        return evaluate_leduc_hand(hand);
+    }
+    let (full_house, flush) = full_house_and_flush(evaluator);
+    let mut value_to_count = [0u8; 13];
+    let mut count_to_value = [0u32; 5];
+    let mut suit_value_sets = [0u32; 4];
+    let mut value_set = 0u32;
+
+    for &card in hand {
+        let v = get_rank(card);
+        value_set |= 1 << v;
+        value_to_count[v] += 1;
+        suit_value_sets[get_suit(card)] |= 1 << v;
+    }
+
+    for (value, &count) in value_to_count.iter().enumerate() {
+        count_to_value[count as usize] |= 1 << value;
+    }
+
+    if let Some(flush_index) = find_flush(&suit_value_sets) {
+        let straight_rank = rank_straight(suit_value_sets[flush_index], evaluator);
+        if straight_rank != 0 {
+            return rank_hand(STRAIGHT_FLUSH, straight_rank);
+        }
+        return rank_hand(flush, keep_n(suit_value_sets[flush_index], 5));
+    } else if count_to_value[4] != 0 {
+        let high = keep_highest(value_set ^ count_to_value[4]);
+        return rank_hand(FOUR_OF_A_KIND, count_to_value[4] << 13 | high);
+    } else if count_to_value[3].count_ones() == 2 {
+        let set = keep_highest(count_to_value[3]);
+        let pair = count_to_value[3] ^ set;
+        return rank_hand(full_house, set << 13 | pair);
+    } else if count_to_value[3] != 0 && count_to_value[2] != 0 {
+        let set = count_to_value[3];
+        let pair = keep_highest(count_to_value[2]);
+        return rank_hand(full_house, set << 13 | pair);
+    } else if rank_straight(value_set, evaluator) != 0 {
+        return rank_hand(STRAIGHT, rank_straight(value_set, evaluator));
+    } else if count_to_value[3] != 0 {
+        let low = keep_n(value_set ^ count_to_value[3], 2);
+        return rank_hand(THREE_OF_A_KIND, count_to_value[3] << 13 | low);
+    } else if count_to_value[2].count_ones() >= 2 {
+        let pairs = keep_n(count_to_value[2], 2);
+        let low = keep_highest(value_set ^ pairs);
+        return rank_hand(TWO_PAIR, pairs << 13 | low);
+    } else if count_to_value[2] != 0 {
+        let pair = count_to_value[2];
+        let low = keep_n(value_set ^ pair, 3);
+        return rank_hand(ONE_PAIR, pair << 13 | low);
+    }
+    rank_hand(HIGH_CARD, keep_n(value_set, 5))
+}
+
+// Score of a player's hole cards with 3 up to 5 community cards. An Omaha hand is made of exactly
+// two hole cards and three community cards
+pub fn evaluate_player_hand(hole_cards: &[u8], community_cards: &[u8], evaluator: Evaluator) -> u32 {
+    match evaluator {
+        Evaluator::Holdem | Evaluator::ShortDeck | Evaluator::Leduc => evaluate_hand(&[hole_cards, community_cards].concat(), evaluator),
+        Evaluator::Omaha => hole_cards.iter()
+            .cloned()
+            .combinations(2)
+            .cartesian_product(community_cards.iter().cloned().combinations(3).collect_vec())
+            .map(|(hole, community)| evaluate_hand(&[hole, community].concat(), evaluator))
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cards::cards_from_string;
+
+    fn score(hand: &str, evaluator: Evaluator) -> u32 {
+        evaluate_hand(&cards_from_string(hand).unwrap(), evaluator)
+    }
+
+    #[test]
+    fn short_deck_wheel_is_the_lowest_straight() {
+        let wheel = score("As6h7d8c9sKhQd", Evaluator::ShortDeck);
+        let six_high = score("6s7h8d9cTsKhQd", Evaluator::ShortDeck);
+        let trips = score("9s9h9dAcKsQhJd", Evaluator::ShortDeck);
+        assert!(trips < wheel && wheel < six_high);
+        // In hold'em the same cards are just ace high
+        assert!(score("As6h7d8c9sKhQd", Evaluator::Holdem) < score("6s6hAdKcQs", Evaluator::Holdem));
+    }
+
+    #[test]
+    fn short_deck_flush_beats_full_house() {
+        let flush = score("AsJs9s7s6sKhKd", Evaluator::ShortDeck);
+        let full_house = score("KsKhKdAcAh7s6d", Evaluator::ShortDeck);
+        assert!(flush > full_house);
+        assert!(score("AsJs9s7s6sKhKd", Evaluator::Holdem) < score("KsKhKdAcAh7s6d", Evaluator::Holdem));
+        // Quads still beat both
+        assert!(score("KsKhKdKcAh7s6d", Evaluator::ShortDeck) > flush);
+    }
+    fn omaha_score(hole_cards: &str, community_cards: &str) -> u32 {
+        evaluate_player_hand(&cards_from_string(hole_cards).unwrap(), &cards_from_string(community_cards).unwrap(), Evaluator::Omaha)
+    }
+
+    #[test]
+    fn omaha_hands_use_exactly_two_hole_cards() {
+        // Four suited hole cards and one suited board card aren't a flush, only two can play. The
+        // best hand is the straight of Q-J with T-9-8
+        let no_flush = omaha_score("AsKsQsJs", "2s7h8d9cTh");
+        assert!(no_flush < score("2s3s4s5s7s", Evaluator::Holdem));
+        assert_eq!(no_flush, score("QsJs8d9cTh", Evaluator::Holdem));
+        // Three suited board cards make a flush with two suited hole cards
+        assert_eq!(omaha_score("AsKsQhJh", "2s7s8s9cTh"), score("AsKs2s7s8s", Evaluator::Holdem));
+        // Four board cards of a straight need two hole cards, so a single one doesn't complete it
+        assert!(omaha_score("9h2c2d3h", "5s6d7c8sKh") < score("5s6d7c8s9h", Evaluator::Holdem));
+        // Quads on the board only play three of them, next to the two best hole cards
+        assert_eq!(omaha_score("2h3d4c5s", "AsAhAdAcKh"), score("5s4cAsAhAd", Evaluator::Holdem));
+    }
+}
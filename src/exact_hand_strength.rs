@@ -0,0 +1,56 @@
+use itertools::Itertools;
+
+use crate::evaluate::evaluate_player_hand;
+use crate::game_def::GameDef;
+use crate::hand_strength_histogram::bin_edges::bin_index;
+
+// Hand strength of a hand for every possible runout of the rest of the board, computed by full
+// enumeration on the CPU. Hand strength is the share of opponent hands beaten, ties counting half,
+// like in the kernels
+pub fn exact_hand_strengths(hand: &[u8], game_def: GameDef) -> Vec<f32> {
+    let board = &hand[game_def.hole_cards..];
+    let remaining_cards = game_def.deck_cards().into_iter().filter(|card| !hand.contains(card)).collect_vec();
+
+    remaining_cards.iter()
+        .cloned()
+        .combinations(game_def.board_cards() - board.len())
+        .map(|runout| runout_hand_strength(hand, &runout, game_def))
+        .collect()
+}
+
+// Hand strength of a hand once `runout` completes its board, against every remaining opponent hand
+pub fn runout_hand_strength(hand: &[u8], runout: &[u8], game_def: GameDef) -> f32 {
+    let (hole_cards, board) = hand.split_at(game_def.hole_cards);
+    let full_board = [board, runout].concat();
+    let player_score = evaluate_player_hand(hole_cards, &full_board, game_def.evaluator);
+    let remaining_cards = game_def.deck_cards()
+        .into_iter()
+        .filter(|card| !hand.contains(card) && !runout.contains(card))
+        .collect_vec();
+
+    let mut opponents_beaten = 0;
+    let mut total_opponent_hands = 0;
+    for opponent_cards in remaining_cards.into_iter().combinations(game_def.hole_cards) {
+        let opponent_score = evaluate_player_hand(&opponent_cards, &full_board, game_def.evaluator);
+        if player_score > opponent_score {
+            opponents_beaten += 2;
+        } else if player_score == opponent_score {
+            opponents_beaten += 1;
+        }
+        total_opponent_hands += 2;
+    }
+
+    opponents_beaten as f32 / total_opponent_hands as f32
+}
+
+// Histogram in percent over the bins given by `bin_edges`, binned the same way as in the kernels
+pub fn hand_strength_histogram(hand_strengths: &[f32], bin_edges: &[f32]) -> Vec<u8> {
+    let mut histogram = vec![0u32; bin_edges.len() - 1];
+    for &hand_strength in hand_strengths {
+        histogram[bin_index(hand_strength, bin_edges)] += 1;
+    }
+
+    histogram.iter()
+        .map(|&bin_value| ((bin_value as f32 / hand_strengths.len() as f32) * 100.0) as u8)
+        .collect()
+}
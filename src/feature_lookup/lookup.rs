@@ -0,0 +1,98 @@
+use std::error::Error;
+use std::rc::Rc;
+use hand_isomorphism_rust::hand_indexer::HandIndexer;
+
+use crate::cards::{cards_from_string, Hand};
+use crate::feature_lookup::lru_cache::LruCache;
+use crate::game_def::GameDef;
+use crate::hand_strength_histogram::load::load_hand_strength_histograms_from_file;
+use crate::isomorphism::{canonical_hand_index, create_hand_indexer};
+use crate::opponent_cluster_hand_strength::load::load_opponent_cluster_hand_strengths_from_file;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FeatureKind {
+    HandStrengthHistogram,
+    OpponentClusterHandStrength,
+}
+
+// Maps arbitrary hands to their canonical index and reads the matching row from the exported
+// batches. Exports are expected in canonical index order, as written for `EnumeratedHandSource`
+// or `HandLoader` batches generated by `generate_canonical_hands`.
+pub struct FeatureLookup {
+    pub feature_kind: FeatureKind,
+    pub round: usize,
+    pub batch_size: usize,
+    pub import_path: String,
+    hand_indexer: HandIndexer,
+    hot_entries: LruCache<u64, Vec<u8>>,
+    batches: LruCache<usize, Rc<Vec<Vec<u8>>>>,
+}
+
+impl FeatureLookup {
+    // `entries_capacity` hot feature rows and `batches_capacity` decoded batch files are kept in memory
+    pub fn new(
+        feature_kind: FeatureKind,
+        round: usize,
+        import_path: &str,
+        entries_capacity: usize,
+        batches_capacity: usize
+    ) -> Result<Self, Box<dyn Error>> {
+        // Every batch except the last one has the size of the first batch
+        let first_batch = load_feature_batch(feature_kind, round, 0, import_path)?;
+        let mut batches = LruCache::new(batches_capacity);
+        let batch_size = first_batch.len();
+        batches.insert(0, Rc::new(first_batch));
+
+        Ok(Self {
+            feature_kind,
+            round,
+            batch_size,
+            import_path: import_path.to_string(),
+            hand_indexer: create_hand_indexer(round, GameDef::holdem()),
+            hot_entries: LruCache::new(entries_capacity),
+            batches,
+        })
+    }
+
+    // Looks up a hand written like "AhKd | 7c8c9c"
+    pub fn lookup(&mut self, hand: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.lookup_cards(&cards_from_string(hand)?)
+    }
+
+    // Looks up the hole cards followed by the board, rejecting repeated cards and cards outside the deck
+    pub fn lookup_cards(&mut self, cards: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        let cards = Hand::from_cards(cards, GameDef::holdem())?.to_cards();
+        if GameDef::holdem().round_from_hand_size(cards.len()) != Some(self.round) {
+            return Err(format!("A hand of {} cards doesn't belong to round {}", cards.len(), self.round).into());
+        }
+
+        let index = canonical_hand_index(&self.hand_indexer, &cards);
+        if let Some(features) = self.hot_entries.get(&index) {
+            return Ok(features.clone());
+        }
+
+        let batch_index = index as usize / self.batch_size;
+        let offset = index as usize % self.batch_size;
+        let batch = match self.batches.get(&batch_index) {
+            Some(batch) => batch.clone(),
+            None => {
+                let batch = Rc::new(load_feature_batch(self.feature_kind, self.round, batch_index, &self.import_path)?);
+                self.batches.insert(batch_index, batch.clone());
+                batch
+            }
+        };
+
+        let features = batch.get(offset)
+            .ok_or(format!("Canonical hand {} is missing from batch {}", index, batch_index))?
+            .clone();
+        self.hot_entries.insert(index, features.clone());
+        Ok(features)
+    }
+}
+
+fn load_feature_batch(feature_kind: FeatureKind, round: usize, batch: usize, import_path: &str) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+    match feature_kind {
+        FeatureKind::HandStrengthHistogram => load_hand_strength_histograms_from_file(round, batch, import_path),
+        FeatureKind::OpponentClusterHandStrength => load_opponent_cluster_hand_strengths_from_file(round, batch, import_path),
+    }
+}
@@ -0,0 +1,91 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+// Least recently used cache, every access moves an entry to the back of `order`
+pub struct LruCache<K, V> {
+    capacity: usize,
+    tick: u64,
+    entries: HashMap<K, (V, u64)>,
+    order: BTreeMap<u64, K>,
+}
+
+impl<K: Hash + Eq + Clone, V> LruCache<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            tick: 0,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+        }
+    }
+
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        let tick = self.next_tick();
+        let (value, last_used) = self.entries.get_mut(key)?;
+        self.order.remove(last_used);
+        self.order.insert(tick, key.clone());
+        *last_used = tick;
+        Some(value)
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        let tick = self.next_tick();
+        if let Some((_, last_used)) = self.entries.get(&key) {
+            self.order.remove(last_used);
+        } else if self.entries.len() >= self.capacity {
+            if let Some((_, evicted_key)) = self.order.pop_first() {
+                self.entries.remove(&evicted_key);
+            }
+        }
+        self.order.insert(tick, key.clone());
+        self.entries.insert(key, (value, tick));
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn least_recently_used_entry_is_evicted() {
+        let mut cache = LruCache::new(2);
+        cache.insert(1, "a");
+        cache.insert(2, "b");
+        // Reading 1 makes 2 the least recently used entry
+        assert_eq!(cache.get(&1), Some(&"a"));
+        cache.insert(3, "c");
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&1), Some(&"a"));
+        assert_eq!(cache.get(&3), Some(&"c"));
+
+        // Overwriting an entry counts as a use and doesn't evict anything
+        cache.insert(1, "d");
+        cache.insert(4, "e");
+        assert_eq!(cache.get(&3), None);
+        assert_eq!(cache.get(&1), Some(&"d"));
+        assert_eq!(cache.get(&4), Some(&"e"));
+    }
+
+    #[test]
+    fn cache_never_holds_more_than_its_capacity() {
+        let mut cache = LruCache::new(3);
+        for key in 0..10 {
+            cache.insert(key, key * 10);
+        }
+        assert_eq!(cache.entries.len(), 3);
+        assert_eq!(cache.order.len(), 3);
+        assert_eq!((0..10).filter(|key| cache.get(key).is_some()).collect::<Vec<_>>(), vec![7, 8, 9]);
+
+        // A capacity of 0 still keeps the last entry
+        let mut cache = LruCache::new(0);
+        cache.insert(1, 1);
+        cache.insert(2, 2);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&2), Some(&2));
+    }
+}
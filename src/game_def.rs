@@ -0,0 +1,123 @@
+// How hands are scored, selected in the kernels with a compiler define
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Evaluator {
+    Holdem,
+    // A-6-7-8-9 is a straight and a flush beats a full house
+    ShortDeck,
+    // A hand uses exactly two hole cards and three board cards
+    Omaha,
+    // Pairing the board beats any unpaired hand, otherwise the highest private card wins. Also
+    // covers Kuhn poker, which has no board. Only evaluated on the CPU
+    Leduc,
+}
+
+// The `N` highest cards of the 52-card deck
+const fn top_cards<const N: usize>() -> [u8; N] {
+    let mut deck = [0u8; N];
+    let mut i = 0;
+    while i < N {
+        deck[i] = (52 - N + i) as u8;
+        i += 1;
+    }
+    deck
+}
+
+static HOLDEM_DECK: [u8; 52] = top_cards::<52>();
+static SHORT_DECK: [u8; 36] = top_cards::<36>();
+// J, Q and K of spades and hearts
+static LEDUC_DECK: [u8; 6] = [36, 37, 40, 41, 44, 45];
+// J, Q and K of spades
+static KUHN_DECK: [u8; 3] = [36, 40, 44];
+
+// Everything the generators, loaders and kernels need to know about a poker variant. Cards keep
+// the `rank << 2 | suit` encoding. The kernels expect the deck to be the highest cards of the
+// 52-card deck, so smaller decks drop the lowest ranks first
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GameDef {
+    pub deck: &'static [u8],
+    pub hole_cards: usize,
+    // Community cards dealt on each street, starting with the preflop (0 cards)
+    pub board_cards_per_street: &'static [usize],
+    pub evaluator: Evaluator,
+}
+
+impl GameDef {
+    pub fn holdem() -> Self {
+        Self { deck: &HOLDEM_DECK, hole_cards: 2, board_cards_per_street: &[0, 3, 1, 1], evaluator: Evaluator::Holdem }
+    }
+
+    // 36-card deck without the 2s up to the 5s
+    pub fn short_deck() -> Self {
+        Self { deck: &SHORT_DECK, hole_cards: 2, board_cards_per_street: &[0, 3, 1, 1], evaluator: Evaluator::ShortDeck }
+    }
+
+    // Pot-limit Omaha with 4 hole cards
+    pub fn omaha() -> Self {
+        Self { deck: &HOLDEM_DECK, hole_cards: 4, board_cards_per_street: &[0, 3, 1, 1], evaluator: Evaluator::Omaha }
+    }
+
+    // 6-card deck, one private card and one board card
+    pub fn leduc() -> Self {
+        Self { deck: &LEDUC_DECK, hole_cards: 1, board_cards_per_street: &[0, 1], evaluator: Evaluator::Leduc }
+    }
+
+    // 3-card deck, one private card and no board
+    pub fn kuhn() -> Self {
+        Self { deck: &KUHN_DECK, hole_cards: 1, board_cards_per_street: &[0], evaluator: Evaluator::Leduc }
+    }
+
+    pub fn rounds(&self) -> usize {
+        self.board_cards_per_street.len()
+    }
+
+    pub fn board_cards(&self) -> usize {
+        self.board_cards_per_street.iter().sum()
+    }
+
+    // Cards dealt per round: the hole cards, then each street of community cards
+    pub fn cards_per_round(&self) -> Vec<usize> {
+        self.board_cards_per_street.iter()
+            .enumerate()
+            .map(|(round, &board_cards)| if round == 0 { self.hole_cards + board_cards } else { board_cards })
+            .collect()
+    }
+
+    pub fn cards_in_round(&self, round: usize) -> usize {
+        self.cards_per_round()[..=round].iter().sum()
+    }
+
+    pub fn round_from_hand_size(&self, hand_size: usize) -> Option<usize> {
+        (0..self.rounds()).find(|&round| self.cards_in_round(round) == hand_size)
+    }
+
+    pub fn deck_cards(&self) -> Vec<u8> {
+        self.deck.to_vec()
+    }
+
+    pub fn contains_card(&self, card: u8) -> bool {
+        self.deck.contains(&card)
+    }
+
+    // Compiler options that parameterise the OpenCL kernels
+    pub fn kernel_build_options(&self) -> String {
+        assert!(self.evaluator != Evaluator::Leduc, "Leduc and Kuhn hands are only evaluated on the CPU");
+        // `initialize_deck` in the kernels deals the cards from `52 - DECK_SIZE` up to 51
+        assert!(
+            self.deck.iter().enumerate().all(|(i, &card)| card as usize == 52 - self.deck.len() + i),
+            "The kernels need the deck to be the {} highest cards in order",
+            self.deck.len()
+        );
+        let evaluator = match self.evaluator {
+            Evaluator::Holdem | Evaluator::Leduc => "",
+            Evaluator::ShortDeck => " -D SHORT_DECK",
+            Evaluator::Omaha => " -D OMAHA",
+        };
+        format!(
+            "-D DECK_SIZE={} -D HOLE_CARDS={} -D BOARD_CARDS={}{}",
+            self.deck.len(),
+            self.hole_cards,
+            self.board_cards(),
+            evaluator
+        )
+    }
+}
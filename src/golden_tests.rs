@@ -0,0 +1,274 @@
+// End-to-end runs of the generators on the fixture hands in `tests/fixtures`, compared against the
+// checked-in golden exports in `tests/fixtures/golden`. They use the CPU backend with a fixed seed,
+// so any change to the CPU ports shows up as a failing test. The kernels themselves are only
+// covered by the `opencl_` tests, which run the river features on the OpenCL backend against the
+// same golden files when a device is available and pass without checking anything otherwise. On
+// the river there is no runout to sample, so the features don't depend on the seed. After an
+// intended change, rerun with `UPDATE_GOLDEN=1 cargo test` to rewrite the golden files and commit
+// them with the change.
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use prost::Message;
+
+use crate::cards::cards_from_string;
+use crate::feature_lookup::lookup::{FeatureKind, FeatureLookup};
+use crate::game_def::GameDef;
+use crate::hand_source::HandSource;
+use crate::hand_strength_histogram::generate::{generate_hand_strength_histograms, AdaptiveTrials, HandStrengthHistogramSettings};
+use crate::hand_strength_histogram::load::{load_hand_strength_histograms_from_file, load_hand_strength_histograms_with_metadata_from_file};
+use crate::hand_strength_histogram::save::save_hand_strength_histograms_to_file;
+use crate::hand_strength_histogram::validate::{validate_hand_strength_histograms, ValidationSettings};
+use crate::kernel::{available_devices, Backend};
+use crate::load::HandLoader;
+use crate::opponent_cluster_hand_strength::generate::{generate_opponent_cluster_hand_strengths, OpponentClusterHandStrengthSettings};
+use crate::opponent_cluster_hand_strength::load::load_opponent_cluster_hand_strengths_from_file;
+use crate::proto::{ExpectedHandStrengths, HandStrengthHistograms, OpponentClusterHandStrengthHistograms};
+use crate::isomorphism::{canonical_hand_index, create_hand_indexer};
+use crate::verify::{verify_hand_strength_histograms, verify_opponent_cluster_hand_strengths};
+
+const SEED: u32 = 42;
+
+fn fixtures_path() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+fn hand_loader(round: usize) -> HandLoader {
+    let folder_path = fixtures_path().join("canonical_hands");
+    HandLoader::from_folder(round, GameDef::holdem(), folder_path.to_str().unwrap())
+        .expect(format!("Failed to load fixture hands for round {}", round).as_str())
+}
+
+// A fresh export folder per test, so tests running in parallel don't share files
+fn export_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("poker-feature-extraction-golden-{}", name));
+    let _ = fs::remove_dir_all(&path);
+    fs::create_dir_all(&path).unwrap();
+    path
+}
+
+// Compares the decoded export `file_name` with its golden file, or overwrites the golden file
+// with the export when `UPDATE_GOLDEN` is set
+fn assert_golden<M: Message + Default + PartialEq + std::fmt::Debug>(export_path: &Path, golden_folder: &str, file_name: &str) {
+    let exported = fs::read(export_path.join(file_name))
+        .expect(format!("Missing export {}/{}", golden_folder, file_name).as_str());
+    let golden_path = fixtures_path().join("golden").join(golden_folder).join(file_name);
+
+    if std::env::var("UPDATE_GOLDEN").is_ok() {
+        fs::create_dir_all(golden_path.parent().unwrap()).unwrap();
+        fs::write(&golden_path, &exported).unwrap();
+        return;
+    }
+
+    let golden = fs::read(&golden_path)
+        .expect(format!("Missing golden file {:?}, run with UPDATE_GOLDEN=1 to create it", golden_path).as_str());
+    assert_eq!(
+        M::decode(&*exported).unwrap(),
+        M::decode(&*golden).unwrap(),
+        "{}/{} differs from its golden file",
+        golden_folder,
+        file_name
+    );
+}
+
+fn check_hand_strength_histograms(round: usize, settings: HandStrengthHistogramSettings, golden_folder: &str) {
+    let export_path = export_path(&format!("{}_round_{}", golden_folder, round));
+    let settings = HandStrengthHistogramSettings {
+        backend: Backend::Cpu { seed: SEED },
+        ..settings
+    };
+
+    generate_hand_strength_histograms(&mut hand_loader(round), &settings, export_path.to_str().unwrap());
+    assert!(verify_hand_strength_histograms(&mut hand_loader(round), export_path.to_str().unwrap()).bad_rows.is_empty());
+
+    let file_name = format!("round_{}_batch_0.bin", round);
+    assert_golden::<HandStrengthHistograms>(&export_path, golden_folder, &file_name);
+    assert_golden::<ExpectedHandStrengths>(
+        &export_path.join("expected_hand_strength"),
+        &format!("{}/expected_hand_strength", golden_folder),
+        &file_name
+    );
+}
+
+fn fixed_trials() -> HandStrengthHistogramSettings {
+    HandStrengthHistogramSettings {
+        trials_per_hand: Some(50),
+        ..HandStrengthHistogramSettings::default()
+    }
+}
+
+#[test]
+fn hand_strength_histograms_preflop() {
+    check_hand_strength_histograms(0, fixed_trials(), "hand_strength_histograms");
+}
+
+#[test]
+fn hand_strength_histograms_flop() {
+    check_hand_strength_histograms(1, fixed_trials(), "hand_strength_histograms");
+}
+
+#[test]
+fn hand_strength_histograms_turn() {
+    check_hand_strength_histograms(2, fixed_trials(), "hand_strength_histograms");
+}
+
+#[test]
+fn hand_strength_histograms_river() {
+    check_hand_strength_histograms(3, fixed_trials(), "hand_strength_histograms");
+}
+
+// The rank table scores hands exactly like the evaluator, so the features match the same goldens
+#[test]
+fn hand_strength_histograms_river_rank_table() {
+    let settings = HandStrengthHistogramSettings { use_rank_table: true, ..fixed_trials() };
+    check_hand_strength_histograms(3, settings, "hand_strength_histograms");
+}
+
+#[test]
+fn hand_strength_histograms_flop_adaptive() {
+    let settings = HandStrengthHistogramSettings {
+        adaptive_trials: Some(AdaptiveTrials { min_trials: 50, max_trials: 500, block_trials: 25, emd_threshold: 0.01 }),
+        ..HandStrengthHistogramSettings::default()
+    };
+    check_hand_strength_histograms(1, settings, "hand_strength_histograms_adaptive");
+}
+
+// The golden river histograms are checked against the exact HS of their hands
+#[test]
+fn hand_strength_histograms_river_validate() {
+    let import_path = fixtures_path().join("golden/hand_strength_histograms");
+    let settings = ValidationSettings::default();
+    let report = validate_hand_strength_histograms(&mut hand_loader(3), import_path.to_str().unwrap(), &settings);
+
+    assert_eq!(report.hands.len(), 6);
+    assert!(report.hands.iter().all(|hand| hand.passed(&settings)), "Max EMD {}", report.max_emd);
+
+    let settings = ValidationSettings { sample_hands: 2, ..ValidationSettings::default() };
+    let report = validate_hand_strength_histograms(&mut hand_loader(3), import_path.to_str().unwrap(), &settings);
+    assert_eq!(report.hands.len(), 2);
+}
+
+fn check_opponent_cluster_hand_strengths(name: &str, use_rank_table: bool) {
+    let export_path = export_path(name);
+    let settings = OpponentClusterHandStrengthSettings {
+        backend: Backend::Cpu { seed: SEED },
+        use_rank_table,
+        ..OpponentClusterHandStrengthSettings::default()
+    };
+    let path_labels = fixtures_path().join("opponent_cluster_labels.bin");
+
+    generate_opponent_cluster_hand_strengths(
+        &mut hand_loader(3),
+        &hand_loader(0),
+        export_path.to_str().unwrap(),
+        path_labels.to_str().unwrap(),
+        &settings
+    );
+    assert!(verify_opponent_cluster_hand_strengths(&mut hand_loader(3), export_path.to_str().unwrap()).bad_rows.is_empty());
+
+    assert_golden::<OpponentClusterHandStrengthHistograms>(&export_path, "opponent_cluster_hand_strengths", "round_3_batch_0.bin");
+}
+
+#[test]
+fn opponent_cluster_hand_strengths_river() {
+    check_opponent_cluster_hand_strengths("opponent_cluster_hand_strengths_river", false);
+}
+
+#[test]
+fn opponent_cluster_hand_strengths_river_rank_table() {
+    check_opponent_cluster_hand_strengths("opponent_cluster_hand_strengths_river_rank_table", true);
+}
+
+// The golden flop histograms rewritten in canonical index order, the layout `FeatureLookup` reads.
+// Only the batches holding a fixture hand are written, every other row is empty
+#[test]
+fn feature_lookup_finds_golden_rows_of_isomorphic_hands() {
+    const BATCH_SIZE: usize = 1000;
+    let import_path = export_path("feature_lookup");
+    let golden = load_hand_strength_histograms_with_metadata_from_file(1, 0, fixtures_path().join("golden/hand_strength_histograms").to_str().unwrap())
+        .unwrap();
+    let hand_indexer = create_hand_indexer(1, GameDef::holdem());
+    let hands = hand_loader(1).current_batch_hands().clone();
+
+    let mut batches: BTreeMap<usize, Vec<Vec<u8>>> = BTreeMap::new();
+    batches.insert(0, vec![vec![]; BATCH_SIZE]);
+    for (hand, row) in hands.iter().zip(&golden.data) {
+        let index = canonical_hand_index(&hand_indexer, hand) as usize;
+        batches.entry(index / BATCH_SIZE).or_insert(vec![vec![]; BATCH_SIZE])[index % BATCH_SIZE] = row.clone();
+    }
+    for (batch, rows) in batches {
+        save_hand_strength_histograms_to_file(rows, &golden.bin_edges, vec![], 1, batch, import_path.to_str().unwrap()).unwrap();
+    }
+
+    let mut feature_lookup = FeatureLookup::new(FeatureKind::HandStrengthHistogram, 1, import_path.to_str().unwrap(), 4, 2).unwrap();
+    let row = &golden.data[hands.iter().position(|hand| *hand == cards_from_string("AhKd5c6c7d").unwrap()).unwrap()];
+    assert_eq!(&feature_lookup.lookup("AhKd|5c6c7d").unwrap(), row);
+    // Same hand with hearts, diamonds and clubs renamed to spades, hearts and diamonds, and the
+    // flop in another order
+    assert_eq!(&feature_lookup.lookup("AsKh | 7h5d6d").unwrap(), row);
+    assert_eq!(&feature_lookup.lookup_cards(&cards_from_string("AsKh7h5d6d").unwrap()).unwrap(), row);
+
+    assert!(feature_lookup.lookup("AhAh|5c6c7d").is_err());
+    assert!(feature_lookup.lookup_cards(&[60, 1, 2, 3, 4]).is_err());
+    assert!(feature_lookup.lookup("AhKd").is_err());
+}
+
+// `ocl` panics while listing platforms when there is no OpenCL runtime, which counts as no device
+fn opencl_available() -> bool {
+    let available = std::panic::catch_unwind(|| !available_devices().is_empty()).unwrap_or(false);
+    if !available {
+        log::warn!("No OpenCL device, skipping the kernel golden test");
+    }
+    available
+}
+
+// Kernel divisions may round differently from the CPU, so values can be off by one
+fn assert_golden_rows_close(exported: &[Vec<u8>], golden: &[Vec<u8>], name: &str) {
+    assert_eq!(exported.len(), golden.len(), "{} has {} rows, its golden file {}", name, exported.len(), golden.len());
+    for (row, (exported_row, golden_row)) in exported.iter().zip(golden).enumerate() {
+        let close = exported_row.len() == golden_row.len()
+            && exported_row.iter().zip(golden_row).all(|(&a, &b)| a.abs_diff(b) <= 1);
+        assert!(close, "{} row {} is {:?}, its golden row {:?}", name, row, exported_row, golden_row);
+    }
+}
+
+#[test]
+fn opencl_hand_strength_histograms_river() {
+    if !opencl_available() {
+        return;
+    }
+    let export_path = export_path("opencl_hand_strength_histograms_river");
+    let settings = HandStrengthHistogramSettings { backend: Backend::OpenCl, ..fixed_trials() };
+
+    generate_hand_strength_histograms(&mut hand_loader(3), &settings, export_path.to_str().unwrap());
+    let golden_path = fixtures_path().join("golden/hand_strength_histograms");
+    assert_golden_rows_close(
+        &load_hand_strength_histograms_from_file(3, 0, export_path.to_str().unwrap()).unwrap(),
+        &load_hand_strength_histograms_from_file(3, 0, golden_path.to_str().unwrap()).unwrap(),
+        "OpenCL river HSH"
+    );
+}
+
+#[test]
+fn opencl_opponent_cluster_hand_strengths_river() {
+    if !opencl_available() {
+        return;
+    }
+    let export_path = export_path("opencl_opponent_cluster_hand_strengths_river");
+    let settings = OpponentClusterHandStrengthSettings { backend: Backend::OpenCl, ..OpponentClusterHandStrengthSettings::default() };
+    let path_labels = fixtures_path().join("opponent_cluster_labels.bin");
+
+    generate_opponent_cluster_hand_strengths(
+        &mut hand_loader(3),
+        &hand_loader(0),
+        export_path.to_str().unwrap(),
+        path_labels.to_str().unwrap(),
+        &settings
+    );
+    let golden_path = fixtures_path().join("golden/opponent_cluster_hand_strengths");
+    assert_golden_rows_close(
+        &load_opponent_cluster_hand_strengths_from_file(3, 0, export_path.to_str().unwrap()).unwrap(),
+        &load_opponent_cluster_hand_strengths_from_file(3, 0, golden_path.to_str().unwrap()).unwrap(),
+        "OpenCL river OCHS"
+    );
+}
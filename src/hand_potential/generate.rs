@@ -0,0 +1,144 @@
+use itertools::Itertools;
+use rayon::prelude::*;
+
+use crate::game_def::{Evaluator, GameDef};
+use crate::hand_source::HandSource;
+use crate::hand_potential::save::save_hand_potentials_to_file;
+use crate::rank_table::RankTable;
+
+const AHEAD: usize = 0;
+const TIED: usize = 1;
+const BEHIND: usize = 2;
+
+fn compare(player_score: u32, opponent_score: u32) -> usize {
+    if player_score > opponent_score {
+        AHEAD
+    } else if player_score == opponent_score {
+        TIED
+    } else {
+        BEHIND
+    }
+}
+
+// Positive and negative potential (Billings et al.) of a flop or turn hand, computed by enumerating
+// every opponent hand and every runout of the board up to the river. `rank_table` has to be built
+// for the evaluator of `game_def`. Hands are scored as a whole, so Omaha isn't supported
+pub fn calculate_hand_potential(hand: &[u8], game_def: GameDef, rank_table: &RankTable) -> (f32, f32) {
+    assert!(game_def.evaluator != Evaluator::Omaha, "Hand potential doesn't support Omaha hands");
+    let board = &hand[game_def.hole_cards..];
+    let remaining_cards = game_def.deck_cards().into_iter().filter(|card| !hand.contains(card)).collect_vec();
+
+    // The player's final score only depends on the runout, so it is evaluated once per runout
+    let runouts = remaining_cards.iter()
+        .cloned()
+        .combinations(game_def.board_cards() - board.len())
+        .map(|runout| {
+            let final_hand = [hand, runout.as_slice()].concat();
+            (runout, rank_table.evaluate(&final_hand))
+        })
+        .collect_vec();
+
+    let player_score = rank_table.evaluate(hand);
+    let mut hand_potential = [[0u64; 3]; 3];
+    let mut hand_potential_total = [0u64; 3];
+
+    for opponent_cards in remaining_cards.iter().cloned().combinations(game_def.hole_cards) {
+        let opponent_hand = [opponent_cards.as_slice(), board].concat();
+        let current = compare(player_score, rank_table.evaluate(&opponent_hand));
+
+        for (runout, player_final_score) in runouts.iter() {
+            if runout.iter().any(|card| opponent_cards.contains(card)) {
+                continue;
+            }
+            let opponent_final_hand = [opponent_hand.as_slice(), runout.as_slice()].concat();
+            let eventual = compare(*player_final_score, rank_table.evaluate(&opponent_final_hand));
+
+            hand_potential[current][eventual] += 1;
+            hand_potential_total[current] += 1;
+        }
+    }
+
+    let hp = hand_potential.map(|row| row.map(|count| count as f64));
+    let total = hand_potential_total.map(|count| count as f64);
+
+    let ppot_denominator = total[BEHIND] + total[TIED] / 2.0;
+    let ppot = if ppot_denominator > 0.0 {
+        (hp[BEHIND][AHEAD] + hp[BEHIND][TIED] / 2.0 + hp[TIED][AHEAD] / 2.0) / ppot_denominator
+    } else {
+        0.0
+    };
+
+    let npot_denominator = total[AHEAD] + total[TIED] / 2.0;
+    let npot = if npot_denominator > 0.0 {
+        (hp[AHEAD][BEHIND] + hp[TIED][BEHIND] / 2.0 + hp[AHEAD][TIED] / 2.0) / npot_denominator
+    } else {
+        0.0
+    };
+
+    (ppot as f32, npot as f32)
+}
+
+pub fn generate_hand_potentials(hand_source: &mut dyn HandSource, export_path: &str) {
+    let round = hand_source.round();
+    let game_def = hand_source.game_def();
+    assert!(
+        game_def.hole_cards == 2 && game_def.evaluator != Evaluator::Omaha,
+        "Hand potential needs 2-card hold'em hands, {:?} is not supported",
+        game_def
+    );
+    assert!(round == 1 || round == 2, "Hand potential is only defined for the flop and turn, not round {}", round);
+    log::info!("Generating hand potentials for round {} in {} batches", round, hand_source.total_batches());
+    let rank_table = RankTable::new(game_def.evaluator);
+
+    for batch_index in 0..hand_source.total_batches() {
+        let hands = hand_source.current_batch_hands();
+
+        let results: Vec<(f32, f32)> = hands.par_iter()
+            .map(|hand| calculate_hand_potential(hand, game_def, &rank_table))
+            .collect();
+
+        log::info!(
+            "Finished batch. Round {}, batch {}/{} hands {}",
+            round,
+            batch_index,
+            hand_source.total_batches()-1,
+            results.len()
+        );
+
+        save_hand_potentials_to_file(results, round, batch_index, export_path)
+            .expect(format!("ERROR: Failed to save hand potentials for round {} batch #{}", round, batch_index).as_str());
+
+        hand_source.load_next_batch();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cards::cards_from_string;
+
+    // A Leduc jack is behind a queen or king and ties the other jack. Of the 4 boards against a queen
+    // or king, only the other jack puts it ahead, and against the jack it stays tied: PPot is
+    // 4 / (16 + 4 / 2) and it can't fall behind from ahead or tied
+    #[test]
+    fn leduc_jack_potential() {
+        let game_def = GameDef::leduc();
+        let rank_table = RankTable::new(game_def.evaluator);
+        let (ppot, npot) = calculate_hand_potential(&cards_from_string("Js").unwrap(), game_def, &rank_table);
+        assert!((ppot - 2.0 / 9.0).abs() < 1e-6, "PPot {}", ppot);
+        assert_eq!(npot, 0.0);
+
+        // The king mirrors it, it only loses to a paired queen or jack
+        let (ppot, npot) = calculate_hand_potential(&cards_from_string("Ks").unwrap(), game_def, &rank_table);
+        assert_eq!(ppot, 0.0);
+        assert!((npot - 2.0 / 9.0).abs() < 1e-6, "NPot {}", npot);
+    }
+
+    #[test]
+    fn made_nut_hand_has_no_potential() {
+        let game_def = GameDef::holdem();
+        let rank_table = RankTable::new(game_def.evaluator);
+        let hand = cards_from_string("AsKsQsJsTs").unwrap();
+        assert_eq!(calculate_hand_potential(&hand, game_def, &rank_table), (0.0, 0.0));
+    }
+}
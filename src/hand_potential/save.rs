@@ -0,0 +1,22 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use prost::Message;
+
+use crate::proto::HandPotentials;
+
+// Each entry is (PPot, NPot) for one hand
+pub fn save_hand_potentials_to_file(hand_potentials: Vec<(f32, f32)>, round: usize, batch: usize, export_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let filepath = format!("{}/round_{}_batch_{}.bin", export_path, round, batch);
+
+    let data = HandPotentials {
+        ppot: hand_potentials.iter().map(|&(ppot, _)| ppot).collect(),
+        npot: hand_potentials.iter().map(|&(_, npot)| npot).collect(),
+    };
+
+    let mut buf = Vec::new();
+    data.encode(&mut buf)?;
+
+    let mut file = BufWriter::new(File::create(filepath)?);
+    file.write_all(&buf)?;
+    Ok(())
+}
@@ -0,0 +1,125 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+use hand_isomorphism_rust::hand_indexer::HandIndexer;
+use rayon::prelude::*;
+
+use crate::game_def::GameDef;
+use crate::isomorphism::{canonical_hand, canonical_hand_count, create_hand_indexer};
+
+// Anything that can feed batches of hands (private cards followed by the known
+// community cards) of a single round to the generators. Sources are `Send` so batches can be
+// loaded on their own thread while the previous one is processed
+pub trait HandSource: Send {
+    fn round(&self) -> usize;
+    fn game_def(&self) -> GameDef;
+    fn total_batches(&self) -> usize;
+    fn current_batch(&self) -> usize;
+    fn current_batch_hands(&self) -> &Vec<Vec<u8>>;
+    // Moves the hands of the current batch out, leaving it empty until the next batch is loaded
+    fn take_current_batch_hands(&mut self) -> Vec<Vec<u8>>;
+    fn load_next_batch(&mut self);
+}
+
+// Enumerates the canonical hands of a round on the fly instead of reading pre-generated batches.
+// Hands with cards outside the deck of the game variant are left out of each batch
+pub struct EnumeratedHandSource {
+    pub batch_size: usize,
+    pub total_batches: usize,
+    pub current_batch: usize,
+    pub total_hands: u64,
+    pub round: usize,
+    pub game_def: GameDef,
+    pub current_batch_hands: Vec<Vec<u8>>,
+}
+
+impl EnumeratedHandSource {
+    pub fn new(round: usize, batch_size: usize, game_def: GameDef) -> Self {
+        let hand_indexer = create_hand_indexer(round, game_def);
+        let total_hands = canonical_hand_count(&hand_indexer, round);
+        let total_batches = (total_hands as usize).div_ceil(batch_size);
+
+        let mut hand_source = Self {
+            batch_size,
+            total_batches,
+            current_batch: 0,
+            total_hands,
+            round,
+            game_def,
+            current_batch_hands: vec![]
+        };
+        hand_source.current_batch_hands = hand_source.enumerate_batch(0);
+        return hand_source;
+    }
+
+    fn enumerate_batch(&self, batch: usize) -> Vec<Vec<u8>> {
+        let start = (batch * self.batch_size) as u64;
+        let end = (start + self.batch_size as u64).min(self.total_hands);
+        let round = self.round;
+        let game_def = self.game_def;
+
+        (start..end).into_par_iter()
+            .map_init(
+                || create_hand_indexer(round, game_def),
+                |hand_indexer: &mut HandIndexer, index| canonical_hand(hand_indexer, round, index, game_def)
+            )
+            .filter(|hand| hand.iter().all(|&card| game_def.contains_card(card)))
+            .collect()
+    }
+}
+
+impl HandSource for EnumeratedHandSource {
+    fn round(&self) -> usize { self.round }
+    fn game_def(&self) -> GameDef { self.game_def }
+    fn total_batches(&self) -> usize { self.total_batches }
+    fn current_batch(&self) -> usize { self.current_batch }
+    fn current_batch_hands(&self) -> &Vec<Vec<u8>> { &self.current_batch_hands }
+    fn take_current_batch_hands(&mut self) -> Vec<Vec<u8>> { std::mem::take(&mut self.current_batch_hands) }
+
+    fn load_next_batch(&mut self) {
+        if self.current_batch < self.total_batches-1 {
+            self.current_batch += 1;
+            self.current_batch_hands = self.enumerate_batch(self.current_batch);
+        }
+    }
+}
+
+// An explicit list of hands, served as a single batch
+pub struct HandListSource {
+    pub round: usize,
+    pub game_def: GameDef,
+    pub hands: Vec<Vec<u8>>,
+}
+
+impl HandListSource {
+    pub fn new(hands: Vec<Vec<u8>>, game_def: GameDef) -> Result<Self, Box<dyn Error>> {
+        let hand_size = hands.first().ok_or("Hand list is empty")?.len();
+        let round = game_def.round_from_hand_size(hand_size)
+            .ok_or(format!("Hands of {} cards don't belong to any round", hand_size))?;
+        if hands.iter().any(|hand| hand.len() != hand_size) {
+            return Err("All hands in a hand list must have the same amount of cards".into());
+        }
+        if hands.iter().flatten().any(|&card| !game_def.contains_card(card)) {
+            return Err(format!("Hand list contains cards that are not in the {:?} deck", game_def).into());
+        }
+
+        Ok(Self { round, game_def, hands })
+    }
+
+    // Reads a JSON array of hands, each an array of cards, like `hands.json`
+    pub fn from_json_file(file_path: &str, game_def: GameDef) -> Result<Self, Box<dyn Error>> {
+        let reader = BufReader::new(File::open(file_path)?);
+        let hands: Vec<Vec<u8>> = serde_json::from_reader(reader)?;
+        Self::new(hands, game_def)
+    }
+}
+
+impl HandSource for HandListSource {
+    fn round(&self) -> usize { self.round }
+    fn game_def(&self) -> GameDef { self.game_def }
+    fn total_batches(&self) -> usize { 1 }
+    fn current_batch(&self) -> usize { 0 }
+    fn current_batch_hands(&self) -> &Vec<Vec<u8>> { &self.hands }
+    fn take_current_batch_hands(&mut self) -> Vec<Vec<u8>> { std::mem::take(&mut self.hands) }
+    fn load_next_batch(&mut self) {}
+}
@@ -0,0 +1,147 @@
+use itertools::Itertools;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use rayon::prelude::*;
+
+use crate::hand_source::HandSource;
+use crate::hand_strength_histogram::cpu::trial_hand_strength;
+use crate::hand_strength_histogram::generate::HandStrengthHistogramSettings;
+use crate::isomorphism::{canonical_hand, canonical_hand_index, create_hand_indexer};
+use crate::rank_table::build_rank_table;
+use crate::sampling::trial_seed;
+
+// Smallest width of a quantile bin, so bins never collapse when many hands share a hand strength
+const MIN_QUANTILE_BIN_WIDTH: f32 = 1e-4;
+
+// How hand strength is split into bins. Edges run from 0 to 1, bin i covers
+// [edges[i], edges[i + 1]) and the top bin also includes 1
+pub enum BinEdges {
+    // Equally wide bins
+    Uniform,
+    // `num_bins + 1` ascending edges from 0 to 1
+    Custom(Vec<f32>),
+    // Edges at the quantiles of the hand strengths of `sample_hands` random canonical hands of the
+    // round, each on `runouts_per_hand` random runouts, so every bin holds about the same share of the
+    // round. Hands are drawn in proportion to the size of their isomorphism class and measured with
+    // the same opponents as the kernel. The same `seed` always gives the same edges
+    Quantile { sample_hands: usize, runouts_per_hand: usize, seed: u64 },
+}
+
+pub fn uniform_bin_edges(num_bins: usize) -> Vec<f32> {
+    (0..=num_bins).map(|i| i as f32 / num_bins as f32).collect()
+}
+
+// Same as the kernels' `find_bin`
+pub fn bin_index(hand_strength: f32, bin_edges: &[f32]) -> usize {
+    bin_edges[1..bin_edges.len() - 1].partition_point(|&edge| edge <= hand_strength)
+}
+
+// Earth mover's distance between two distributions over the same bins, with the mass of a bin at
+// its center
+pub fn earth_movers_distance(a: &[f32], b: &[f32], bin_edges: &[f32]) -> f32 {
+    let bin_centers = bin_edges.windows(2).map(|edges| (edges[0] + edges[1]) / 2.0).collect_vec();
+    let mut cumulative_difference = 0.0;
+    let mut emd = 0.0;
+    for bin in 0..a.len() - 1 {
+        cumulative_difference += a[bin] - b[bin];
+        emd += cumulative_difference.abs() * (bin_centers[bin + 1] - bin_centers[bin]);
+    }
+    emd
+}
+
+pub fn resolve_bin_edges(settings: &HandStrengthHistogramSettings, hand_source: &dyn HandSource) -> Vec<f32> {
+    let num_bins = settings.num_bins;
+    match &settings.bin_edges {
+        BinEdges::Uniform => uniform_bin_edges(num_bins),
+        BinEdges::Custom(edges) => {
+            assert!(edges.len() == num_bins + 1, "Expected {} bin edges for {} bins, got {}", num_bins + 1, num_bins, edges.len());
+            assert!(
+                edges[0] == 0.0 && edges[num_bins] == 1.0 && edges.windows(2).all(|pair| pair[0] <= pair[1]),
+                "Bin edges have to ascend from 0 to 1: {:?}",
+                edges
+            );
+            edges.clone()
+        },
+        BinEdges::Quantile { sample_hands, runouts_per_hand, seed } => {
+            quantile_bin_edges(hand_source, settings, *sample_hands, *runouts_per_hand, *seed)
+        },
+    }
+}
+
+fn quantile_bin_edges(
+    hand_source: &dyn HandSource,
+    settings: &HandStrengthHistogramSettings,
+    sample_hands: usize,
+    runouts_per_hand: usize,
+    seed: u64
+) -> Vec<f32> {
+    let game_def = hand_source.game_def();
+    let round = hand_source.round();
+    let num_bins = settings.num_bins;
+    log::info!("Sampling hand strengths of {} hands for quantile bin edges of round {}", sample_hands, round);
+
+    // A uniformly random deal lands in each isomorphism class in proportion to its size, so
+    // canonicalizing random deals samples the canonical hands of the whole round by class size
+    let hand_indexer = create_hand_indexer(round, game_def);
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut deck = game_def.deck_cards();
+    let hands = (0..sample_hands)
+        .map(|_| {
+            let (deal, _) = deck.partial_shuffle(&mut rng, game_def.cards_in_round(round));
+            canonical_hand(&hand_indexer, round, canonical_hand_index(&hand_indexer, deal), game_def)
+        })
+        .collect_vec();
+
+    let deck = game_def.deck_cards();
+    let rank_table = build_rank_table(game_def, settings.use_rank_table);
+    let mut hand_strengths: Vec<f32> = hands.par_iter()
+        .enumerate()
+        .flat_map_iter(|(hand_id, hand)| {
+            (0..runouts_per_hand)
+                .map(|runout| trial_hand_strength(hand, &deck, game_def, settings, rank_table.as_ref(), trial_seed(seed as u32, hand_id as u32, runout as u32)))
+                .collect_vec()
+        })
+        .collect();
+    assert!(!hand_strengths.is_empty(), "No hand strengths sampled for quantile bin edges");
+    hand_strengths.sort_by(|a, b| a.total_cmp(b));
+
+    let mut edges = vec![0.0];
+    edges.extend((1..num_bins).map(|i| hand_strengths[i * hand_strengths.len() / num_bins]));
+    edges.push(1.0);
+    separate_equal_edges(&mut edges);
+    log::info!("Quantile bin edges: {:?}", edges);
+    edges
+}
+
+// Moves edges apart until every bin is at least `MIN_QUANTILE_BIN_WIDTH` wide. Quantiles repeat
+// where many hands share a hand strength, like 0 and 1 on the river, which would leave empty bins
+// of zero width. Edges are pushed up first and, where that would pass 1, pulled down from the top
+fn separate_equal_edges(edges: &mut [f32]) {
+    let last = edges.len() - 1;
+    for i in 1..last {
+        edges[i] = edges[i].max(edges[i - 1] + MIN_QUANTILE_BIN_WIDTH);
+    }
+    for i in (1..last).rev() {
+        edges[i] = edges[i].min(edges[i + 1] - MIN_QUANTILE_BIN_WIDTH);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equal_quantile_edges_are_moved_apart() {
+        // River quantiles with a lot of mass at exactly 0 and 1
+        let mut edges = vec![0.0, 0.0, 0.0, 0.4, 0.7, 1.0, 1.0, 1.0];
+        separate_equal_edges(&mut edges);
+
+        assert_eq!(edges[0], 0.0);
+        assert_eq!(edges[edges.len() - 1], 1.0);
+        assert!(edges.windows(2).all(|pair| pair[1] - pair[0] >= MIN_QUANTILE_BIN_WIDTH * 0.99), "{:?}", edges);
+        assert_eq!(edges[3..5], [0.4, 0.7]);
+        assert_eq!(bin_index(0.0, &edges), 0);
+        assert_eq!(bin_index(1.0, &edges), edges.len() - 2);
+    }
+}
@@ -0,0 +1,171 @@
+use itertools::Itertools;
+use rayon::prelude::*;
+
+use crate::game_def::{Evaluator, GameDef};
+use crate::hand_strength_histogram::bin_edges::{bin_index, earth_movers_distance};
+use crate::hand_strength_histogram::generate::{AdaptiveTrials, HandStrengthHistogramSettings};
+use crate::rank_table::{evaluate_player_hand_with, RankTable};
+use crate::sampling::{draw_random_card, draw_runout, trial_seed};
+
+// CPU port of `simulate_poker_hands` in kernel.cl. Hand i runs with the RNG streams of work item i
+// of a chunk started with `seed`, and the results have the kernel's layout: `num_bins` counts,
+// (mean HS, mean HS^2) and the trials run per hand. Hands are scored with `rank_table` if there is
+// one, like the kernel built with `-D RANK_TABLE`
+pub fn simulate_hand_strength_histograms(
+    hands: &[Vec<u8>],
+    game_def: GameDef,
+    settings: &HandStrengthHistogramSettings,
+    rank_table: Option<&RankTable>,
+    bin_edges: &[f32],
+    trials: AdaptiveTrials,
+    seed: u32
+) -> (Vec<i32>, Vec<f32>, Vec<u32>) {
+    let results = hands.par_iter()
+        .enumerate()
+        .map(|(hand_id, hand)| simulate_hand(hand, hand_id as u32, game_def, settings, rank_table, bin_edges, trials, seed))
+        .collect::<Vec<_>>();
+
+    let histograms = results.iter().flat_map(|(histogram, _, _)| histogram.iter().cloned()).collect();
+    let hand_strength_moments = results.iter().flat_map(|(_, moments, _)| moments.iter().cloned()).collect();
+    let trials_used = results.iter().map(|&(_, _, hand_trials)| hand_trials).collect();
+    (histograms, hand_strength_moments, trials_used)
+}
+
+fn simulate_hand(
+    hand: &[u8],
+    hand_id: u32,
+    game_def: GameDef,
+    settings: &HandStrengthHistogramSettings,
+    rank_table: Option<&RankTable>,
+    bin_edges: &[f32],
+    trials: AdaptiveTrials,
+    seed: u32
+) -> (Vec<i32>, [f32; 2], u32) {
+    let deck = game_def.deck_cards();
+
+    let mut histogram = vec![0i32; bin_edges.len() - 1];
+    let mut hand_strength_sum = 0.0f32;
+    let mut hand_strength_squared_sum = 0.0f32;
+    let mut block_start_histogram = histogram.clone();
+    let mut trials_used = 0;
+
+    for trial in 0..trials.max_trials {
+        let hand_strength = trial_hand_strength(hand, &deck, game_def, settings, rank_table, trial_seed(seed, hand_id, trial as u32));
+
+        hand_strength_sum += hand_strength;
+        hand_strength_squared_sum += hand_strength * hand_strength;
+        histogram[bin_index(hand_strength, bin_edges)] += 1;
+
+        trials_used = trial + 1;
+        if trials_used % trials.block_trials == 0 {
+            if trials_used >= trials.min_trials && trials_used > trials.block_trials {
+                let normalize = |counts: &[i32], total: usize| counts.iter().map(|&count| count as f32 / total as f32).collect_vec();
+                let emd = earth_movers_distance(
+                    &normalize(&histogram, trials_used),
+                    &normalize(&block_start_histogram, trials_used - trials.block_trials),
+                    bin_edges
+                );
+                if emd < trials.emd_threshold {
+                    break;
+                }
+            }
+            block_start_histogram.copy_from_slice(&histogram);
+        }
+    }
+
+    (
+        histogram,
+        [hand_strength_sum / trials_used as f32, hand_strength_squared_sum / trials_used as f32],
+        trials_used as u32
+    )
+}
+
+// HS of one trial of the kernel: a runout drawn from `state`, then HS against every weighted
+// opponent hand or against sampled opponent deals
+pub fn trial_hand_strength(
+    hand: &[u8],
+    deck: &[u8],
+    game_def: GameDef,
+    settings: &HandStrengthHistogramSettings,
+    rank_table: Option<&RankTable>,
+    mut state: u32
+) -> f32 {
+    let hole_cards = &hand[..game_def.hole_cards];
+    let runout_size = game_def.board_cards() - (hand.len() - game_def.hole_cards);
+    let enumerate_opponents = game_def.evaluator != Evaluator::Omaha && settings.num_opponents == 1;
+
+    let runout = draw_runout(deck, hand, runout_size, &mut state);
+    let community_cards = [&hand[game_def.hole_cards..], runout.as_slice()].concat();
+    let remaining_cards = deck.iter()
+        .cloned()
+        .filter(|card| !hand.contains(card) && !runout.contains(card))
+        .collect_vec();
+    let player_score = evaluate_player_hand_with(rank_table, hole_cards, &community_cards, game_def.evaluator);
+
+    if enumerate_opponents {
+        let mut opponents_beaten = 0.0f32;
+        let mut total_opponent_hands = 0.0f32;
+        for (i, &card_a) in remaining_cards.iter().enumerate() {
+            for &card_b in &remaining_cards[i + 1..] {
+                let opponent_score = evaluate_player_hand_with(rank_table, &[card_a, card_b], &community_cards, game_def.evaluator);
+                let weight = settings.opponent_range.weights[card_a as usize * 52 + card_b as usize];
+
+                if player_score > opponent_score {
+                    opponents_beaten += 2.0 * weight;
+                } else if player_score == opponent_score {
+                    opponents_beaten += weight;
+                }
+                total_opponent_hands += 2.0 * weight;
+            }
+        }
+
+        if total_opponent_hands > 0.0 { opponents_beaten / total_opponent_hands } else { 0.0 }
+    } else {
+        sample_multi_opponent_strength(&remaining_cards, &community_cards, player_score, game_def, settings, rank_table, &mut state)
+    }
+}
+
+// Same as the kernel's `sample_multi_opponent_strength`
+fn sample_multi_opponent_strength(
+    remaining_cards: &[u8],
+    community_cards: &[u8],
+    player_score: u32,
+    game_def: GameDef,
+    settings: &HandStrengthHistogramSettings,
+    rank_table: Option<&RankTable>,
+    state: &mut u32
+) -> f32 {
+    let mut equity = 0.0f32;
+    let mut total_weight = 0.0f32;
+
+    for _ in 0..settings.opponent_samples {
+        let mut opponent_cards = remaining_cards.to_vec();
+        let mut opponent_cards_count = opponent_cards.len();
+        let mut lost = false;
+        let mut ties = 0;
+        let mut weight = 1.0f32;
+
+        for _ in 0..settings.num_opponents {
+            let opponent_hole_cards = (0..game_def.hole_cards)
+                .map(|_| draw_random_card(&mut opponent_cards, &mut opponent_cards_count, state))
+                .collect_vec();
+            if game_def.evaluator != Evaluator::Omaha {
+                weight *= settings.opponent_range.weights[opponent_hole_cards[0] as usize * 52 + opponent_hole_cards[1] as usize];
+            }
+            let opponent_score = evaluate_player_hand_with(rank_table, &opponent_hole_cards, community_cards, game_def.evaluator);
+
+            if opponent_score > player_score {
+                lost = true;
+            } else if opponent_score == player_score {
+                ties += 1;
+            }
+        }
+
+        if !lost {
+            equity += weight / (ties + 1) as f32;
+        }
+        total_weight += weight;
+    }
+
+    if total_weight > 0.0 { equity / total_weight } else { 0.0 }
+}
@@ -1,18 +1,94 @@
+use std::fs;
+use std::sync::Mutex;
 use itertools::Itertools;
+use ocl::{Buffer, Kernel};
 use ocl::builders::{BufferBuilder, KernelBuilder};
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::kernel::KernelContainer;
-use crate::load::HandLoader;
-use crate::hand_strength_histogram::save::save_hand_strength_histograms_to_file;
+use crate::kernel::{run_chunks_on_devices, Backend, KernelContainer};
+use crate::pipeline::run_batches_pipelined;
+use crate::tuning::{launch_settings, LaunchSettings};
+use crate::hand_source::HandSource;
+use crate::game_def::Evaluator;
+use crate::opponent_range::OpponentRange;
+use crate::rank_table::{build_rank_table, rank_table_kernel_data};
+use crate::hand_strength_histogram::bin_edges::{resolve_bin_edges, BinEdges};
+use crate::hand_strength_histogram::cpu::simulate_hand_strength_histograms;
+use crate::hand_strength_histogram::save::{save_expected_hand_strengths_to_file, save_hand_strength_histograms_to_file};
+use crate::verify::{find_bad_rows, hand_strength_histogram_row_problem, log_bad_rows};
 
-pub fn generate_hand_strength_histograms(round: usize, export_path: &str) {
-    let mut hand_loader = HandLoader::new(round).expect("Failed to initialize HandLoader for round");
+pub struct HandStrengthHistogramSettings {
+    // Number of hand strength bins per histogram
+    pub num_bins: usize,
+    // Where the bins start and end
+    pub bin_edges: BinEdges,
+    // Hand strength is measured against this many random opponent hands
+    pub num_opponents: usize,
+    // Opponent deals sampled per trial when playing against more than one opponent
+    pub opponent_samples: usize,
+    // Weights of the opponent hands hand strength is measured against
+    pub opponent_range: OpponentRange,
+    // Look 7-card hands up in a precomputed rank table instead of evaluating them bitwise
+    pub use_rank_table: bool,
+    // Board runouts per hand, by default 5000 / 1000 / 700 / 400 from preflop to river
+    pub trials_per_hand: Option<usize>,
+    // Stop sampling a hand once its histogram converges instead of always running `trials_per_hand`
+    pub adaptive_trials: Option<AdaptiveTrials>,
+    pub backend: Backend,
+}
+
+impl Default for HandStrengthHistogramSettings {
+    fn default() -> Self {
+        Self {
+            num_bins: 30,
+            bin_edges: BinEdges::Uniform,
+            num_opponents: 1,
+            opponent_samples: 200,
+            opponent_range: OpponentRange::uniform(),
+            use_rank_table: false,
+            trials_per_hand: None,
+            adaptive_trials: None,
+            backend: Backend::OpenCl,
+        }
+    }
+}
+
+// Trials run in blocks of `block_trials`. Sampling stops after the first block that moves the
+// histogram (normalized to the trials so far) by less than `emd_threshold`, but never before
+// `min_trials` and at the latest after `max_trials`
+#[derive(Clone, Copy, Debug)]
+pub struct AdaptiveTrials {
+    pub min_trials: usize,
+    pub max_trials: usize,
+    pub block_trials: usize,
+    pub emd_threshold: f32,
+}
 
+impl AdaptiveTrials {
+    // Always exactly `trials` trials
+    pub fn fixed(trials: usize) -> Self {
+        Self {
+            min_trials: trials,
+            max_trials: trials,
+            block_trials: trials,
+            emd_threshold: 0.0,
+        }
+    }
+}
 
-    log::info!("Initialized HandLoader with round {} and batch 0/{}", round, hand_loader.total_batches-1);
+impl Default for AdaptiveTrials {
+    fn default() -> Self {
+        Self {
+            min_trials: 200,
+            max_trials: 5000,
+            block_trials: 100,
+            emd_threshold: 0.005,
+        }
+    }
+}
 
-    let trials_per_hand = if round == 0 {
+pub fn default_trials_per_hand(round: usize) -> usize {
+    if round == 0 {
         5000
     } else if round == 1 {
         1000
@@ -20,109 +96,348 @@ pub fn generate_hand_strength_histograms(round: usize, export_path: &str) {
         700
     } else {
         400
-    };
-    let cards_per_hand = if round == 0 { 2 } else if round == 1 { 5 } else if round == 2 { 6 } else { 7 };
-
-    let src = include_str!("./kernel.cl");
-    let kernel_container = KernelContainer::new(src);
-    let max_work_group_size = kernel_container.device.max_wg_size().unwrap();
-    let gpu_chunk_size = max_work_group_size * 32;
-    log::info!("Set max group size to {}", gpu_chunk_size);
-    
-    for batch_index in 0..hand_loader.total_batches {
-        let mut hands_analyzed = 0;
-        let current_batch_hands = hand_loader.current_batch_hands.len();
-
-        let hands = &hand_loader.current_batch_hands;
-        let mut results: Vec<Vec<u8>> = vec![];
-
-        for (gpu_batch_index, chunk) in hands.chunks(gpu_chunk_size).enumerate() {
-            let hands_data_flattened = chunk.to_vec().concat();
-
-            // Number of hands and trials
-            let num_hands = chunk.len();
-
-            let mut histograms: Vec<i32> = vec![0; num_hands * 30];
-
-            let hands_buffer = BufferBuilder::<u8>::new()
-                .flags(ocl::flags::MEM_READ_ONLY)
-                .len(hands_data_flattened.len())
-                .copy_host_slice(&hands_data_flattened)
-                .context(&kernel_container.context)
-                .build().unwrap();
-
-            let histograms_buffer = BufferBuilder::<i32>::new()
-                .flags(ocl::flags::MEM_READ_WRITE)
-                .len(histograms.len())
-                .context(&kernel_container.context)
-                .build().unwrap();
-
-            // Generate a seed based on the current time
-            let seed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as u32 + gpu_batch_index as u32;
-
-            // Setup kernel
-            let kernel = KernelBuilder::new()
-                .program(&kernel_container.program)
-                .name("simulate_poker_hands")
-                .arg(&hands_buffer)
-                .arg(&histograms_buffer)
-                .arg(num_hands as u32)
-                .arg(trials_per_hand as u32)
-                .arg(cards_per_hand as u32)
-                .arg(seed)
-                .build()
-                .unwrap();
-
-            unsafe { 
-                kernel
-                    .cmd()
-                    .queue(&kernel_container.queue)
-                    .global_work_size(gpu_chunk_size)
-                    .enq()
-                    .unwrap();
-            }
+    }
+}
+
+// Besides the histograms, EHS, EHS^2 and the variance of HS over the same rollouts are
+// exported as float32 per hand to `{export_path}/expected_hand_strength`. The histograms record
+// the trials run for every hand, which only differ between hands with `adaptive_trials`
+pub fn generate_hand_strength_histograms(hand_source: &mut dyn HandSource, settings: &HandStrengthHistogramSettings, export_path: &str) {
+    let round = hand_source.round();
+    let game_def = hand_source.game_def();
+    assert!((1..=5).contains(&settings.num_opponents), "Hand strength supports 1 to 5 opponents");
+    assert!(settings.num_bins > 0, "Hand strength histograms need at least one bin");
+    assert!(
+        game_def.evaluator != Evaluator::Omaha || settings.opponent_range.weights == OpponentRange::uniform().weights,
+        "Opponent ranges only support 2-card hands, Omaha needs the uniform range"
+    );
+    let num_bins = settings.num_bins;
+    let export_path_expected_hand_strength = format!("{}/expected_hand_strength", export_path);
+    fs::create_dir_all(&export_path_expected_hand_strength).expect("Failed to create expected hand strength export folder");
+    log::info!(
+        "Generating {}-bin hand strength histograms for round {} against {} opponent(s) in {} batches on {:?}",
+        num_bins,
+        round,
+        settings.num_opponents,
+        hand_source.total_batches(),
+        settings.backend
+    );
 
-            kernel_container.queue.finish().unwrap();
+    let trials = settings.adaptive_trials
+        .unwrap_or(AdaptiveTrials::fixed(settings.trials_per_hand.unwrap_or(default_trials_per_hand(round))));
+    assert!(
+        trials.block_trials > 0 && trials.min_trials <= trials.max_trials,
+        "Invalid trial limits {:?}",
+        trials
+    );
+    let bin_edges = resolve_bin_edges(settings, hand_source);
+
+    let (gpu, cpu_rank_table) = match settings.backend {
+        Backend::OpenCl => (Some(HandStrengthHistogramGpu::new(hand_source, settings, trials, &bin_edges)), None),
+        Backend::Cpu { .. } => (None, build_rank_table(game_def, settings.use_rank_table)),
+    };
 
-            // Read the data back into a Rust vector
-            histograms_buffer.cmd().queue(&kernel_container.queue).read(&mut histograms).enq().unwrap();
+    let total_batches = hand_source.total_batches();
+    run_batches_pipelined(
+        hand_source,
+        |batch_index, hands| {
+            let (histograms, hand_strength_moments, trials_used) = match (&gpu, settings.backend) {
+                (Some(gpu), _) => gpu.run_batch(batch_index, total_batches, hands),
+                (None, Backend::Cpu { seed }) => simulate_hand_strength_histograms(
+                    hands,
+                    game_def,
+                    settings,
+                    cpu_rank_table.as_ref(),
+                    &bin_edges,
+                    trials,
+                    seed.wrapping_add(batch_index as u32)
+                ),
+                (None, Backend::OpenCl) => unreachable!(),
+            };
 
-            let histograms_unflattened_normalized = histograms.chunks(30)
-                .map(|chunk| {
+            let histograms_unflattened_normalized = histograms.chunks(num_bins)
+                .zip(trials_used.iter())
+                .map(|(chunk, &hand_trials)| {
                     chunk.iter().map(|&bin_value| {
-                        let normalized = ((bin_value as f32 / trials_per_hand as f32) * 100.0) as u8;
+                        let normalized = ((bin_value as f32 / hand_trials as f32) * 100.0) as u8;
                         return normalized
                     }).collect::<Vec<u8>>()
                 })
                 .collect_vec();
 
-            if histograms_unflattened_normalized[histograms_unflattened_normalized.len()-1].iter().map(|&ch| ch as u32).sum::<u32>() == 0 {
-                log::error!(
-                    "Last histogram (and probably others) is not correctly filled. Round {}, batch {}/{} gpu batch {}",
+            let bad_rows = find_bad_rows(batch_index, &histograms_unflattened_normalized, hands.len(), |row| {
+                hand_strength_histogram_row_problem(row, num_bins)
+            });
+            log_bad_rows("HSH", round, &bad_rows);
+
+            if settings.adaptive_trials.is_some() {
+                log::info!(
+                    "Round {} batch {}/{} used {:.0} trials per hand on average",
                     round,
                     batch_index,
-                    hand_loader.total_batches-1,
-                    gpu_batch_index
+                    total_batches-1,
+                    trials_used.iter().map(|&hand_trials| hand_trials as f64).sum::<f64>() / trials_used.len().max(1) as f64
                 );
             }
 
-            hands_analyzed += histograms_unflattened_normalized.len();
+            let expected_hand_strengths = hand_strength_moments.chunks(2)
+                .map(|moments| {
+                    let (ehs, ehs_squared) = (moments[0], moments[1]);
+                    (ehs, ehs_squared, (ehs_squared - ehs * ehs).max(0.0))
+                })
+                .collect_vec();
+
+            (histograms_unflattened_normalized, trials_used, expected_hand_strengths)
+        },
+        |batch_index, (results, trials_used, results_expected_hand_strength): (Vec<Vec<u8>>, Vec<u32>, Vec<(f32, f32, f32)>)| {
+            save_hand_strength_histograms_to_file(results, &bin_edges, trials_used, round, batch_index, export_path)
+                .expect(format!("ERROR: Failed to save HSH for round {} batch #{}", round, batch_index).as_str());
+            save_expected_hand_strengths_to_file(results_expected_hand_strength, round, batch_index, &export_path_expected_hand_strength)
+                .expect(format!("ERROR: Failed to save EHS for round {} batch #{}", round, batch_index).as_str());
+        }
+    );
+}
+
+// Devices, launch settings and kernels of the OpenCL backend
+struct HandStrengthHistogramGpu {
+    round: usize,
+    kernel_containers: Vec<KernelContainer>,
+    device_launch_settings: Vec<LaunchSettings>,
+    // Each device is only used by one thread at a time, the mutex just makes that visible to the compiler
+    device_kernels: Vec<Mutex<HandStrengthHistogramKernel>>,
+    gpu_chunk_size: usize,
+}
+
+impl HandStrengthHistogramGpu {
+    fn new(hand_source: &dyn HandSource, settings: &HandStrengthHistogramSettings, trials: AdaptiveTrials, bin_edges: &[f32]) -> Self {
+        let round = hand_source.round();
+        let cards_per_hand = hand_source.game_def().cards_in_round(round);
+
+        let src = concat!(include_str!("../kernel_common.cl"), include_str!("./kernel.cl"));
+        let (rank_table, rank_table_build_options) = rank_table_kernel_data(hand_source.game_def(), settings.use_rank_table);
+        let build_options = format!(
+            "{} -D NUM_BINS={}{}",
+            hand_source.game_def().kernel_build_options(),
+            settings.num_bins,
+            rank_table_build_options
+        );
+        let kernel_containers = KernelContainer::for_selected_devices(src, &build_options);
+
+        // Tuned on the hands of the first batch
+        let kernel_key = format!(
+            "hand_strength_histogram round {} opponents {} samples {} trials {} {}",
+            round,
+            settings.num_opponents,
+            settings.opponent_samples,
+            trials.max_trials,
+            build_options
+        );
+        let sample_hands = hand_source.current_batch_hands();
+        let device_launch_settings = kernel_containers.iter()
+            .map(|kernel_container| launch_settings(
+                kernel_container,
+                &kernel_key,
+                |chunk_size| (
+                    HandStrengthHistogramKernel::new(kernel_container, chunk_size, cards_per_hand, trials, settings, &rank_table, bin_edges),
+                    sample_hands.iter().cycle().take(chunk_size).cloned().collect_vec()
+                ),
+                |(kernel, _)| &kernel.kernel,
+                |(kernel, hands), candidate| { kernel.run(kernel_container, hands, candidate, 0); }
+            ))
+            .collect_vec();
+        // Chunks are shared between the devices, so they're sized for the smallest one
+        let gpu_chunk_size = device_launch_settings.iter().map(|launch_settings| launch_settings.chunk_size).min().unwrap();
+        log::info!("Set chunk size to {} on {} device(s)", gpu_chunk_size, kernel_containers.len());
+
+        let device_kernels = kernel_containers.iter()
+            .map(|kernel_container| Mutex::new(HandStrengthHistogramKernel::new(
+                kernel_container,
+                gpu_chunk_size,
+                cards_per_hand,
+                trials,
+                settings,
+                &rank_table,
+                bin_edges
+            )))
+            .collect_vec();
+
+        Self {
+            round,
+            kernel_containers,
+            device_launch_settings,
+            device_kernels,
+            gpu_chunk_size,
+        }
+    }
+
+    // Histogram counts, HS moments and trials of a batch, its chunks spread over the devices
+    fn run_batch(&self, batch_index: usize, total_batches: usize, hands: &[Vec<u8>]) -> (Vec<i32>, Vec<f32>, Vec<u32>) {
+        let chunks = hands.chunks(self.gpu_chunk_size).collect_vec();
+
+        let chunk_results = run_chunks_on_devices(self.kernel_containers.len(), &chunks, |device_index, gpu_batch_index, chunk| {
+            let device_kernel = self.device_kernels[device_index].lock().unwrap();
+            let (histograms, hand_strength_moments, trials_used) = device_kernel.run(
+                &self.kernel_containers[device_index],
+                chunk,
+                self.device_launch_settings[device_index],
+                gpu_batch_index
+            );
+
             log::info!(
-                "Finished GPU batch. Round {}, batch {}/{} gpu batch {} hands {}/{} in batch",
-                round,
+                "Finished GPU batch. Round {}, batch {}/{} gpu batch {}/{} on device {}",
+                self.round,
                 batch_index,
-                hand_loader.total_batches-1,
+                total_batches-1,
                 gpu_batch_index,
-                hands_analyzed,
-                current_batch_hands
+                chunks.len()-1,
+                device_index
             );
 
-            results.extend(histograms_unflattened_normalized);
+            (histograms, hand_strength_moments, trials_used)
+        });
+
+        let (histograms, hand_strength_moments, trials_used): (Vec<_>, Vec<_>, Vec<_>) = chunk_results.into_iter().multiunzip();
+        (histograms.concat(), hand_strength_moments.concat(), trials_used.concat())
+    }
+}
+
+// The kernel of one device with its buffers. Constant data is uploaded once and the per-chunk
+// buffers are sized for a full chunk, so running a chunk only writes the hands and sets the
+// `num_hands` and `seed` arguments
+struct HandStrengthHistogramKernel {
+    num_bins: usize,
+    kernel: Kernel,
+    hands_buffer: Buffer<u8>,
+    histograms_buffer: Buffer<i32>,
+    hand_strength_moments_buffer: Buffer<f32>,
+    trials_used_buffer: Buffer<u32>,
+    _constant_buffers: (Buffer<f32>, Buffer<u32>, Buffer<f32>),
+}
+
+impl HandStrengthHistogramKernel {
+    fn new(
+        kernel_container: &KernelContainer,
+        gpu_chunk_size: usize,
+        cards_per_hand: usize,
+        trials: AdaptiveTrials,
+        settings: &HandStrengthHistogramSettings,
+        rank_table: &[u32],
+        bin_edges: &[f32]
+    ) -> Self {
+        let hands_buffer = BufferBuilder::<u8>::new()
+            .flags(ocl::flags::MEM_READ_ONLY)
+            .len(gpu_chunk_size * cards_per_hand)
+            .context(&kernel_container.context)
+            .build().unwrap();
+
+        let histograms_buffer = BufferBuilder::<i32>::new()
+            .flags(ocl::flags::MEM_READ_WRITE)
+            .len(gpu_chunk_size * settings.num_bins)
+            .context(&kernel_container.context)
+            .build().unwrap();
+
+        let hand_strength_moments_buffer = BufferBuilder::<f32>::new()
+            .flags(ocl::flags::MEM_WRITE_ONLY)
+            .len(gpu_chunk_size * 2)
+            .context(&kernel_container.context)
+            .build().unwrap();
+
+        let trials_used_buffer = BufferBuilder::<u32>::new()
+            .flags(ocl::flags::MEM_WRITE_ONLY)
+            .len(gpu_chunk_size)
+            .context(&kernel_container.context)
+            .build().unwrap();
+
+        let opponent_weights_buffer = BufferBuilder::<f32>::new()
+            .flags(ocl::flags::MEM_READ_ONLY)
+            .len(settings.opponent_range.weights.len())
+            .copy_host_slice(&settings.opponent_range.weights)
+            .context(&kernel_container.context)
+            .build().unwrap();
+
+        let rank_table_buffer = BufferBuilder::<u32>::new()
+            .flags(ocl::flags::MEM_READ_ONLY)
+            .len(rank_table.len())
+            .copy_host_slice(rank_table)
+            .context(&kernel_container.context)
+            .build().unwrap();
+
+        let bin_edges_buffer = BufferBuilder::<f32>::new()
+            .flags(ocl::flags::MEM_READ_ONLY)
+            .len(bin_edges.len())
+            .copy_host_slice(bin_edges)
+            .context(&kernel_container.context)
+            .build().unwrap();
+
+        let kernel = KernelBuilder::new()
+            .program(&kernel_container.program)
+            .name("simulate_poker_hands")
+            .arg(&hands_buffer)
+            .arg(&histograms_buffer)
+            .arg(&hand_strength_moments_buffer)
+            .arg(&trials_used_buffer)
+            .arg(&opponent_weights_buffer)
+            .arg(&rank_table_buffer)
+            .arg(&bin_edges_buffer)
+            .arg_named("num_hands", 0u32)
+            .arg(trials.min_trials as u32)
+            .arg(trials.max_trials as u32)
+            .arg(trials.block_trials as u32)
+            .arg(trials.emd_threshold)
+            .arg(cards_per_hand as u32)
+            .arg(settings.num_opponents as u32)
+            .arg(settings.opponent_samples as u32)
+            .arg_named("seed", 0u32)
+            .build()
+            .unwrap();
+
+        Self {
+            num_bins: settings.num_bins,
+            kernel,
+            hands_buffer,
+            histograms_buffer,
+            hand_strength_moments_buffer,
+            trials_used_buffer,
+            _constant_buffers: (opponent_weights_buffer, rank_table_buffer, bin_edges_buffer),
         }
+    }
+    // Histogram counts, HS moments and trials of every hand in `hands`, at most one full chunk
+    fn run(&self, kernel_container: &KernelContainer, hands: &[Vec<u8>], launch_settings: LaunchSettings, gpu_batch_index: usize) -> (Vec<i32>, Vec<f32>, Vec<u32>) {
+        let hands_data_flattened = hands.concat();
+
+        // Number of hands and trials
+        let num_hands = hands.len();
+
+        let mut histograms: Vec<i32> = vec![0; num_hands * self.num_bins];
+        let mut hand_strength_moments: Vec<f32> = vec![0.0; num_hands * 2];
+        let mut trials_used: Vec<u32> = vec![0; num_hands];
+
+        // The last chunk of a batch only fills the start of the buffers
+        self.hands_buffer.cmd().queue(&kernel_container.queue).write(&hands_data_flattened).enq().unwrap();
+
+        // Generate a seed based on the current time
+        let seed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as u32 + gpu_batch_index as u32;
+        self.kernel.set_arg("num_hands", num_hands as u32).unwrap();
+        self.kernel.set_arg("seed", seed).unwrap();
+
+        unsafe { 
+            let mut kernel_cmd = self.kernel
+                .cmd()
+                .queue(&kernel_container.queue)
+                .global_work_size(launch_settings.global_work_size(num_hands));
+            if let Some(local_size) = launch_settings.local_size {
+                kernel_cmd = kernel_cmd.local_work_size(local_size);
+            }
+            kernel_cmd.enq().unwrap();
+        }
+
+        kernel_container.queue.finish().unwrap();
 
-        save_hand_strength_histograms_to_file(results, round, batch_index, export_path)
-            .expect(format!("ERROR: Failed to save HSH for round {} batch #{}", round, batch_index).as_str());
+        // Read the data back into a Rust vector
+        self.histograms_buffer.cmd().queue(&kernel_container.queue).read(&mut histograms).enq().unwrap();
+        self.hand_strength_moments_buffer.cmd().queue(&kernel_container.queue).read(&mut hand_strength_moments).enq().unwrap();
+        self.trials_used_buffer.cmd().queue(&kernel_container.queue).read(&mut trials_used).enq().unwrap();
 
-        hand_loader.load_next_batch();
+        (histograms, hand_strength_moments, trials_used)
     }
 }
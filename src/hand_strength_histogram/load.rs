@@ -0,0 +1,35 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use prost::Message;
+
+use crate::hand_strength_histogram::bin_edges::uniform_bin_edges;
+use crate::proto::HandStrengthHistograms;
+
+pub fn load_hand_strength_histograms_from_file(round: usize, batch: usize, import_path: &str) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+    let filepath = format!("{}/round_{}_batch_{}.bin", import_path, round, batch);
+
+    let mut file = BufReader::new(File::open(filepath)?);
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf)?;
+
+    let data = HandStrengthHistograms::decode(&*buf)?;
+    Ok(data.data)
+}
+
+// Histograms together with the bin edges they were generated with and the trials of every hand.
+// Files from before the bin edges were stored have none, those histograms always use uniform bins.
+// Files from before the trials were stored have none either
+pub fn load_hand_strength_histograms_with_metadata_from_file(round: usize, batch: usize, import_path: &str) -> Result<HandStrengthHistograms, Box<dyn Error>> {
+    let filepath = format!("{}/round_{}_batch_{}.bin", import_path, round, batch);
+
+    let mut file = BufReader::new(File::open(filepath)?);
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf)?;
+
+    let mut data = HandStrengthHistograms::decode(&*buf)?;
+    if let (true, Some(histogram)) = (data.bin_edges.is_empty(), data.data.first()) {
+        data.bin_edges = uniform_bin_edges(histogram.len());
+    }
+    Ok(data)
+}
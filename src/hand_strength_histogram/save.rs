@@ -2,13 +2,35 @@ use std::fs::File;
 use std::io::{BufWriter, Write};
 use prost::Message;
 
-use crate::proto::HandStrengthHistograms;
+use crate::proto::{ExpectedHandStrengths, HandStrengthHistograms};
 
-pub fn save_hand_strength_histograms_to_file(hand_strength_histograms: Vec<Vec<u8>>, round: usize, batch: usize, export_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+// `bin_edges` are stored with every batch so the histograms can be read without the settings,
+// `trials` holds the trials each histogram was sampled from
+pub fn save_hand_strength_histograms_to_file(hand_strength_histograms: Vec<Vec<u8>>, bin_edges: &[f32], trials: Vec<u32>, round: usize, batch: usize, export_path: &str) -> Result<(), Box<dyn std::error::Error>> {
     let filepath = format!("{}/round_{}_batch_{}.bin", export_path, round, batch);
 
     let data = HandStrengthHistograms {
         data: hand_strength_histograms,
+        bin_edges: bin_edges.to_vec(),
+        trials,
+    };
+
+    let mut buf = Vec::new();
+    data.encode(&mut buf)?;
+
+    let mut file = BufWriter::new(File::create(filepath)?);
+    file.write_all(&buf)?;
+    Ok(())
+}
+
+// Each entry is (EHS, EHS^2, variance of HS) for one hand
+pub fn save_expected_hand_strengths_to_file(expected_hand_strengths: Vec<(f32, f32, f32)>, round: usize, batch: usize, export_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let filepath = format!("{}/round_{}_batch_{}.bin", export_path, round, batch);
+
+    let data = ExpectedHandStrengths {
+        ehs: expected_hand_strengths.iter().map(|&(ehs, _, _)| ehs).collect(),
+        ehs_squared: expected_hand_strengths.iter().map(|&(_, ehs_squared, _)| ehs_squared).collect(),
+        variance: expected_hand_strengths.iter().map(|&(_, _, variance)| variance).collect(),
     };
 
     let mut buf = Vec::new();
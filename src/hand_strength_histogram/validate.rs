@@ -0,0 +1,164 @@
+use itertools::Itertools;
+use rayon::prelude::*;
+
+use crate::cards::cards_to_string;
+use crate::exact_hand_strength::exact_hand_strengths;
+use crate::hand_source::HandSource;
+use crate::hand_strength_histogram::bin_edges::{bin_index, earth_movers_distance};
+use crate::hand_strength_histogram::generate::default_trials_per_hand;
+use crate::hand_strength_histogram::load::load_hand_strength_histograms_with_metadata_from_file;
+
+pub struct ValidationSettings {
+    // Hands checked per round, spread evenly over the batches. With more batches than hands, the
+    // first `sample_hands` batches get one hand each
+    pub sample_hands: usize,
+    // Trials the histograms were generated with, for files that don't record them. By default the
+    // generator's default for the round
+    pub trials_per_hand: Option<usize>,
+    // Largest accepted earth mover's distance between generated and exact histograms, in HS. Sampling
+    // noise alone reaches about 0.025 for drawing hands at the default trials
+    pub max_emd: f32,
+    // Width of the per-bin confidence intervals in standard deviations. With 30 bins, 3.5 keeps the
+    // share of correct hands flagged by chance around 1%
+    pub confidence_z: f32,
+}
+
+impl Default for ValidationSettings {
+    fn default() -> Self {
+        Self {
+            sample_hands: 100,
+            trials_per_hand: None,
+            max_emd: 0.04,
+            confidence_z: 3.5,
+        }
+    }
+}
+
+pub struct HandValidation {
+    pub batch: usize,
+    pub index: usize,
+    pub hand: Vec<u8>,
+    pub emd: f32,
+    // Bins where the generated share is outside the confidence interval around the exact share
+    pub bins_outside: Vec<usize>,
+}
+
+impl HandValidation {
+    pub fn passed(&self, settings: &ValidationSettings) -> bool {
+        self.emd <= settings.max_emd && self.bins_outside.is_empty()
+    }
+}
+
+pub struct ValidationReport {
+    pub hands: Vec<HandValidation>,
+    pub mean_emd: f32,
+    pub max_emd: f32,
+}
+
+// Compares generated histograms of a sample of hands with their exact HS distribution over every
+// runout of the board. The exact distribution is against one uniformly random opponent, so only
+// histograms generated with the default `num_opponents` and `opponent_range` are expected to pass.
+// Full enumeration is quick from the flop on, a preflop hand takes minutes
+pub fn validate_hand_strength_histograms(hand_source: &mut dyn HandSource, import_path: &str, settings: &ValidationSettings) -> ValidationReport {
+    let round = hand_source.round();
+    let game_def = hand_source.game_def();
+    let total_batches = hand_source.total_batches();
+    let default_trials = settings.trials_per_hand.unwrap_or(default_trials_per_hand(round));
+    let hands_per_batch = settings.sample_hands.div_ceil(total_batches).max(1);
+    log::info!(
+        "Validating hand strength histograms of round {} against exact HS for {} hands per batch in {} batches",
+        round,
+        hands_per_batch,
+        total_batches
+    );
+
+    let mut hands = Vec::new();
+    for batch_index in 0..total_batches {
+        let batch_hands = hand_source.current_batch_hands();
+        let hand_strength_histograms = load_hand_strength_histograms_with_metadata_from_file(round, batch_index, import_path)
+            .expect(format!("ERROR: Failed to load HSH for round {} batch #{}", round, batch_index).as_str());
+        let (histograms, bin_edges) = (&hand_strength_histograms.data, &hand_strength_histograms.bin_edges);
+        assert_eq!(
+            histograms.len(),
+            batch_hands.len(),
+            "Round {} batch #{} has {} histograms for {} hands",
+            round,
+            batch_index,
+            histograms.len(),
+            batch_hands.len()
+        );
+
+        let step = (batch_hands.len() / hands_per_batch).max(1);
+        let batch_sample_hands = hands_per_batch.min(settings.sample_hands - hands.len());
+        let sampled_indices = (0..batch_hands.len()).step_by(step).take(batch_sample_hands).collect_vec();
+        let batch_validations: Vec<HandValidation> = sampled_indices.par_iter()
+            .map(|&index| {
+                let hand = &batch_hands[index];
+                let exact = exact_histogram(&exact_hand_strengths(hand, game_def), bin_edges);
+                let trials = hand_strength_histograms.trials.get(index).map(|&trials| trials as usize).unwrap_or(default_trials);
+                let generated_sum = histograms[index].iter().map(|&bin_value| bin_value as f32).sum::<f32>().max(1.0);
+                let generated = histograms[index].iter().map(|&bin_value| bin_value as f32 / generated_sum).collect_vec();
+
+                let bins_outside = (0..exact.len())
+                    .filter(|&bin| {
+                        // Generated bins are percentages rounded down, so they can be up to one
+                        // percent below the sampled share
+                        let p = exact[bin];
+                        let margin = settings.confidence_z * (p * (1.0 - p) / trials as f32).sqrt();
+                        let generated_share = histograms[index][bin] as f32 / 100.0;
+                        generated_share < p - margin - 0.01 || generated_share > p + margin
+                    })
+                    .collect_vec();
+
+                HandValidation {
+                    batch: batch_index,
+                    index,
+                    hand: hand.clone(),
+                    emd: earth_movers_distance(&generated, &exact, bin_edges),
+                    bins_outside,
+                }
+            })
+            .collect();
+
+        log::info!("Validated batch. Round {}, batch {}/{} hands {}", round, batch_index, total_batches-1, batch_validations.len());
+        hands.extend(batch_validations);
+        if hands.len() >= settings.sample_hands {
+            break;
+        }
+        hand_source.load_next_batch();
+    }
+
+    let mean_emd = hands.iter().map(|hand| hand.emd).sum::<f32>() / hands.len().max(1) as f32;
+    let max_emd = hands.iter().map(|hand| hand.emd).fold(0.0, f32::max);
+    let failed_hands = hands.iter().filter(|hand| !hand.passed(settings)).collect_vec();
+    for hand in failed_hands.iter() {
+        log::warn!(
+            "Hand {} (batch {} index {}) is outside the tolerance: EMD {:.4}, bins outside the confidence interval {:?}",
+            cards_to_string(&hand.hand),
+            hand.batch,
+            hand.index,
+            hand.emd,
+            hand.bins_outside
+        );
+    }
+    log::info!(
+        "Validated {} hands of round {}: mean EMD {:.4}, max EMD {:.4}, {} outside the tolerance",
+        hands.len(),
+        round,
+        mean_emd,
+        max_emd,
+        failed_hands.len()
+    );
+
+    ValidationReport { hands, mean_emd, max_emd }
+}
+
+// Share of the hand strengths in every bin, without the rounding of the exported histograms
+fn exact_histogram(hand_strengths: &[f32], bin_edges: &[f32]) -> Vec<f32> {
+    let mut histogram = vec![0.0; bin_edges.len() - 1];
+    for &hand_strength in hand_strengths {
+        histogram[bin_index(hand_strength, bin_edges)] += 1.0;
+    }
+
+    histogram.iter().map(|&bin_value| bin_value / hand_strengths.len() as f32).collect()
+}
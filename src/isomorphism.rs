@@ -0,0 +1,27 @@
+use hand_isomorphism_rust::hand_indexer::HandIndexer;
+
+use crate::game_def::GameDef;
+
+// Builds an indexer that covers all rounds up to and including `round`, dealing the private
+// cards first and then every street of community cards
+pub fn create_hand_indexer(round: usize, game_def: GameDef) -> HandIndexer {
+    let cards_per_round = game_def.cards_per_round()[..=round].iter().map(|&cards| cards as u8).collect();
+    HandIndexer::init((round + 1) as u32, cards_per_round)
+}
+
+// Amount of canonical hands in a round (169 / 1,286,792 / 55,190,538 / 2,428,287,420 for hold'em)
+pub fn canonical_hand_count(hand_indexer: &HandIndexer, round: usize) -> u64 {
+    hand_indexer.size(round as u32)
+}
+
+pub fn canonical_hand(hand_indexer: &HandIndexer, round: usize, index: u64, game_def: GameDef) -> Vec<u8> {
+    let mut cards = vec![0u8; game_def.cards_in_round(round)];
+    hand_indexer.hand_unindex(round as u32, index, &mut cards);
+    return cards;
+}
+
+// Index of the canonical (suit isomorphic) representative of a hand, the hand being
+// its private cards followed by the community cards known in that round
+pub fn canonical_hand_index(hand_indexer: &HandIndexer, cards: &[u8]) -> u64 {
+    hand_indexer.hand_index_last(cards)
+}
@@ -1,3 +1,13 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Where the generators run their kernels. `Cpu` runs a port of the kernels on the CPU with a fixed
+// seed, which is slow but gives the same features on every machine, like for the golden tests
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    OpenCl,
+    Cpu { seed: u32 },
+}
+
 pub struct KernelContainer {
     pub platform: ocl::Platform,
     pub device: ocl::Device,
@@ -7,9 +17,15 @@ pub struct KernelContainer {
 }
 
 impl KernelContainer {
-    pub fn new(source: &str) -> Self {
-        let platform = ocl::Platform::default();
-        let device = ocl::Device::first(platform).unwrap();
+    // One container per device selected by `OPENCL_DEVICES`
+    pub fn for_selected_devices(source: &str, build_options: &str) -> Vec<Self> {
+        selected_devices().into_iter()
+            .map(|(platform, device)| Self::for_device(source, build_options, platform, device))
+            .collect()
+    }
+
+    pub fn for_device(source: &str, build_options: &str, platform: ocl::Platform, device: ocl::Device) -> Self {
+        log::info!("Building kernels for {}", device.name().unwrap_or_default());
         let context = ocl::Context::builder()
             .platform(platform)
             .devices(device.clone())
@@ -17,6 +33,7 @@ impl KernelContainer {
         let program = ocl::Program::builder()
             .devices(device)
             .src(source)
+            .cmplr_opt(build_options)
             .build(&context).unwrap();
         let queue = ocl::Queue::new(&context, device, None).unwrap();
         return Self {
@@ -28,3 +45,122 @@ impl KernelContainer {
         }
     }
 }
+
+// Every device of every platform, with the indices `OPENCL_DEVICES` refers to them by
+pub fn available_devices() -> Vec<(usize, ocl::Platform, usize, ocl::Device)> {
+    ocl::Platform::list().into_iter()
+        .enumerate()
+        .flat_map(|(platform_index, platform)| {
+            ocl::Device::list_all(platform).unwrap_or_default().into_iter()
+                .enumerate()
+                .map(move |(device_index, device)| (platform_index, platform, device_index, device))
+        })
+        .collect()
+}
+
+pub fn log_available_devices() {
+    for (platform_index, platform, device_index, device) in available_devices() {
+        log::info!(
+            "OpenCL device {}:{} {} ({})",
+            platform_index,
+            device_index,
+            device.name().unwrap_or_default(),
+            platform.name().unwrap_or_default()
+        );
+    }
+}
+
+// `OPENCL_DEVICES` is a comma separated list of devices, each given as `platform:device` indices
+// (see `log_available_devices`) or as part of the device name. Without it the first device of the
+// default platform is used
+pub fn selected_devices() -> Vec<(ocl::Platform, ocl::Device)> {
+    let selection = match std::env::var("OPENCL_DEVICES") {
+        Ok(selection) if !selection.trim().is_empty() => selection,
+        _ => {
+            let platform = ocl::Platform::default();
+            return vec![(platform, ocl::Device::first(platform).unwrap())];
+        }
+    };
+
+    let devices = available_devices();
+    selection.split(',')
+        .map(|selector| selector.trim())
+        .map(|selector| {
+            let by_index = selector.split_once(':')
+                .and_then(|(platform, device)| Some((platform.parse::<usize>().ok()?, device.parse::<usize>().ok()?)));
+            devices.iter()
+                .find(|(platform_index, _, device_index, device)| match by_index {
+                    Some(index) => index == (*platform_index, *device_index),
+                    None => device.name().unwrap_or_default().contains(selector),
+                })
+                .map(|&(_, platform, _, device)| (platform, device))
+                .expect(format!("No OpenCL device matches '{}' in OPENCL_DEVICES", selector).as_str())
+        })
+        .collect()
+}
+
+// Runs `run_chunk(device_index, chunk_index, chunk)` for every chunk, with one thread per device
+// taking the next chunk whenever it's done with the last one. Results are returned in chunk order
+pub fn run_chunks_on_devices<T: Sync, R: Send>(
+    num_devices: usize,
+    chunks: &[T],
+    run_chunk: impl Fn(usize, usize, &T) -> R + Sync
+) -> Vec<R> {
+    let next_chunk = AtomicUsize::new(0);
+    let mut results: Vec<(usize, R)> = std::thread::scope(|scope| {
+        let workers = (0..num_devices)
+            .map(|device_index| {
+                let next_chunk = &next_chunk;
+                let run_chunk = &run_chunk;
+                scope.spawn(move || {
+                    let mut device_results = vec![];
+                    loop {
+                        let chunk_index = next_chunk.fetch_add(1, Ordering::Relaxed);
+                        if chunk_index >= chunks.len() {
+                            break;
+                        }
+                        device_results.push((chunk_index, run_chunk(device_index, chunk_index, &chunks[chunk_index])));
+                    }
+                    device_results
+                })
+            })
+            .collect::<Vec<_>>();
+        workers.into_iter().flat_map(|worker| worker.join().unwrap()).collect()
+    });
+
+    results.sort_by_key(|(chunk_index, _)| *chunk_index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn chunk_results_come_back_in_chunk_order() {
+        let chunks = (0..40).collect::<Vec<usize>>();
+        let runs = Mutex::new(vec![]);
+        // Uneven chunk times so the workers finish out of order and take turns on the chunks
+        let results = run_chunks_on_devices(3, &chunks, |device_index, chunk_index, &chunk| {
+            std::thread::sleep(Duration::from_millis(((chunk * 7 + device_index * 3) % 5) as u64));
+            runs.lock().unwrap().push((device_index, chunk_index));
+            chunk * 2
+        });
+
+        assert_eq!(results, chunks.iter().map(|chunk| chunk * 2).collect::<Vec<_>>());
+        let mut runs = runs.into_inner().unwrap();
+        assert!(runs.iter().map(|&(device_index, _)| device_index).collect::<HashSet<_>>().len() > 1);
+        runs.sort_by_key(|&(_, chunk_index)| chunk_index);
+        assert_eq!(runs.iter().map(|&(_, chunk_index)| chunk_index).collect::<Vec<_>>(), chunks);
+    }
+
+    #[test]
+    fn no_chunks_give_no_results() {
+        let results = run_chunks_on_devices(2, &Vec::<usize>::new(), |_, _, &chunk| chunk);
+        assert!(results.is_empty());
+    }
+}
@@ -0,0 +1,353 @@
+// Assumed constants and types
+#define NUM_RANKS 13
+#define NUM_SUITS 4
+
+#define GET_RANK(card) (card >> 2)
+
+// The game definition comes in as compiler options (see `GameDef::kernel_build_options`),
+// these defaults describe hold'em
+#ifndef DECK_SIZE
+#define DECK_SIZE 52
+#endif
+#ifndef HOLE_CARDS
+#define HOLE_CARDS 2
+#endif
+#ifndef BOARD_CARDS
+#define BOARD_CARDS 5
+#endif
+#define HAND_CARDS (HOLE_CARDS + BOARD_CARDS)
+
+// A smaller deck drops the lowest cards first, short deck (6+) removes the 2s up to the 5s
+#define DECK_OFFSET (52 - DECK_SIZE)
+
+// Assume constants defined for hand types
+#define STRAIGHT_FLUSH 8
+#define FOUR_OF_A_KIND 7
+#ifdef SHORT_DECK // A flush beats a full house in short deck
+#define FLUSH 6
+#define FULL_HOUSE 5
+#else
+#define FULL_HOUSE 6
+#define FLUSH 5
+#endif
+#define STRAIGHT 4
+#define THREE_OF_A_KIND 3
+#define TWO_PAIR 2
+#define ONE_PAIR 1
+#define HIGH_CARD 0
+
+
+// 
+// RNG
+// 
+
+unsigned int xorshift(unsigned int *state) {
+    *state ^= *state << 13;
+    *state ^= *state >> 17;
+    *state ^= *state << 5;
+    return *state;
+}
+
+// Integer hash (lowbias32), so neighbouring hands and trials get unrelated RNG streams
+unsigned int hash_uint(unsigned int x) {
+    x ^= x >> 16;
+    x *= 0x7feb352dU;
+    x ^= x >> 15;
+    x *= 0x846ca68bU;
+    x ^= x >> 16;
+    return x;
+}
+
+// Starting state for the RNG of one trial of one hand. xorshift never leaves 0, so 0 is replaced
+unsigned int trial_seed(unsigned int seed, unsigned int hand_id, unsigned int trial) {
+    unsigned int state = hash_uint(seed ^ hash_uint(hand_id ^ hash_uint(trial)));
+    return state == 0 ? 0x9e3779b9U : state;
+}
+
+// Uniform in [0, bound) without modulo bias (Lemire's multiply-shift). The retry only happens
+// with probability below bound / 2^32
+unsigned int bounded_random(unsigned int bound, unsigned int *state) {
+    unsigned int x = xorshift(state);
+    unsigned int low = x * bound;
+    if (low < bound) {
+        unsigned int threshold = (0U - bound) % bound;
+        while (low < threshold) {
+            x = xorshift(state);
+            low = x * bound;
+        }
+    }
+    return mul_hi(x, bound);
+}
+
+// 
+// UTILS
+// 
+
+// Function to copy from global to private memory
+void copy_global_to_private(__private uchar* dst, __global const uchar* src, int count) {
+    for (int i = 0; i < count; i++) {
+        dst[i] = src[i];
+    }
+}
+
+// Function to copy within private memory
+void copy_private_to_private(__private uchar* dst, __private const uchar* src, int count) {
+    for (int i = 0; i < count; i++) {
+        dst[i] = src[i];
+    }
+}
+
+// Keep only the most significant bit
+uint keep_highest(uint rank) {
+    return 1 << (31 - clz(rank));
+}
+
+
+// 
+// DECK
+// 
+
+uchar get_suit(uchar card) {
+    // ours: s, h, d, c
+    // the library we copied the hand eval from: s, c, h ,d
+    uchar rank_ours = (card) & 3;
+    if (rank_ours == 1) return 2;
+    if (rank_ours == 2) return 3;
+    if (rank_ours == 3) return 1;
+
+    return rank_ours;
+}
+
+// Initializes the deck of cards
+void initialize_deck(uchar *deck) {
+    for (int i = 0; i < DECK_SIZE; i++) {
+        deck[i] = (uchar)(DECK_OFFSET + i); // Initialize card values from DECK_OFFSET to 51
+    }
+}
+
+// Removes hand cards from the deck
+void remove_hand_cards(uchar *deck, __global const uchar* hand_cards, int num_hand_cards) {
+    for (int i = 0; i < num_hand_cards; i++) {
+        for (int j = 0; j < DECK_SIZE; j++) {
+            if (deck[j] == hand_cards[i]) {
+                deck[j] = 255; // Mark the card as removed by setting it to an invalid value
+                break;
+            }
+        }
+    }
+}
+
+// Copies the cards of `deck` that aren't marked as removed to the front of `remaining_cards`
+int collect_remaining_cards(const uchar *deck, uchar *remaining_cards) {
+    int remaining_count = 0;
+    for (int i = 0; i < DECK_SIZE; i++) {
+        if (deck[i] != 255) remaining_cards[remaining_count++] = deck[i];
+    }
+    return remaining_count;
+}
+
+// One step of a partial Fisher-Yates shuffle: takes a uniformly drawn card out of the first
+// `*remaining_count` cards by moving the last one into its place
+uchar draw_random_card(uchar *remaining_cards, int *remaining_count, unsigned int *seed) {
+    unsigned int index = bounded_random(*remaining_count, seed);
+    uchar card = remaining_cards[index];
+    remaining_cards[index] = remaining_cards[--(*remaining_count)];
+    return card;
+}
+
+// Fills the board up to BOARD_CARDS and marks the drawn cards as removed in `deck`
+void draw_community_cards(uchar *deck, uchar *community_cards, int known_cards_count, unsigned int *seed) {
+    uchar remaining_cards[DECK_SIZE];
+    int remaining_count = collect_remaining_cards(deck, remaining_cards);
+    for (int count = known_cards_count; count < BOARD_CARDS; count++) {
+        uchar card = draw_random_card(remaining_cards, &remaining_count, seed);
+        community_cards[count] = card;
+        deck[card - DECK_OFFSET] = 255; // initialize_deck puts every card at card - DECK_OFFSET
+    }
+}
+
+// 
+// EVALUATION
+//
+
+uint rank_hand(uint hand_type, uint card_details) {
+    // printf("card_details: %d \n", card_details);
+    return (hand_type << 27) | card_details;
+}
+
+// Identifying and ranking a straight in a set of card values
+uint rank_straight(uint value_set) {
+    uint left = value_set & (value_set << 1) & (value_set << 2) & (value_set << 3) & (value_set << 4);
+    int idx = clz(left);
+    if (idx < 32) {
+        uint highest_card_rank = 32 - 4 - idx; // Highest card in the straight
+        return (STRAIGHT << 27) | (1 << highest_card_rank); // Encode as a straight
+    }
+#ifdef SHORT_DECK
+    else if ((value_set & 0b1000011110000) == 0b1000011110000) { // Check for a wheel (A-6-7-8-9)
+        return (STRAIGHT << 27) | (1 << 4); // Ace is treated as low, so "4" represents the 9
+    }
+#else
+    else if ((value_set & 0b1000000001111) == 0b1000000001111) { // Check for a wheel (A-2-3-4-5)
+        return (STRAIGHT << 27) | (1 << 3); // Ace is treated as low, so "3" represents the 5
+    }
+#endif
+    return 0; // Return zero to indicate no straight, zero is a valid return since no hand type uses it
+}
+
+// Keeping the N highest bits
+uint keep_n(uint rank, uint to_keep) {
+    while (popcount(rank) > to_keep) {
+        rank &= rank - 1; // Remove the least significant bit
+    }
+    return rank;
+}
+
+int find_flush(const uint *suit_value_sets) {
+    for (int i = 0; i < 4; i++) {
+        if (popcount(suit_value_sets[i]) >= 5) { // Using pop count to find at least 5 cards of same suit
+            return i;
+        }
+    }
+    return -1;
+}
+
+// Hand evaluation function - make sure it handles the right address space
+int evaluate_hand(__private const uchar* hand, const int hand_size) {
+    uchar value_to_count[13] = {0};
+    uint count_to_value[5] = {0};
+    uint suit_value_sets[4] = {0};
+    uint value_set = 0;
+
+    for (int i = 0; i < hand_size; i++) {
+        uchar card = hand[i];
+        uchar v = GET_RANK(card);
+        uchar s = get_suit(card);
+        value_set |= 1 << v;
+        value_to_count[v]++;
+        suit_value_sets[s] |= 1 << v;
+    }
+
+    // Convert value counts to another form for easier processing
+    for (int i = 0; i < 13; i++) {
+        uchar count = value_to_count[i];
+        count_to_value[count] |= 1 << i;
+    }    
+
+    // Initialize variables for evaluating hands
+    int flush_index = find_flush(suit_value_sets);
+
+    uint rank_result = 0;
+
+    if (flush_index != -1) {
+        uint straight_rank = rank_straight(suit_value_sets[flush_index]); // Only a straight within the flush suit counts
+        if (straight_rank != 0) { // If a straight is found
+            // printf("straight flush %d\n", rank_hand(STRAIGHT_FLUSH, straight_rank));
+            return rank_hand(STRAIGHT_FLUSH, straight_rank);
+        }
+        uint rank = keep_n(suit_value_sets[flush_index], 5);
+        // printf("flush %d \n", rank_hand(FLUSH, rank));
+        return rank_hand(FLUSH, rank);
+    } else if (count_to_value[4] != 0) {
+        uint high = keep_highest(value_set ^ count_to_value[4]);
+        // printf("four of a kind %d \n", rank_hand(FOUR_OF_A_KIND, count_to_value[4] << 13 | high));
+        return rank_hand(FOUR_OF_A_KIND, count_to_value[4] << 13 | high);
+    } else if (popcount(count_to_value[3]) == 2) {
+        uint set = keep_highest(count_to_value[3]);
+        uint pair = count_to_value[3] ^ set;
+        // printf("full house 1 %d \n", rank_hand(FULL_HOUSE, set << 13 | pair));
+        return rank_hand(FULL_HOUSE, set << 13 | pair);
+    } else if (count_to_value[3] != 0 && count_to_value[2] != 0) {
+        uint set = count_to_value[3];
+        uint pair = keep_highest(count_to_value[2]);
+        // printf("full house 2 %d \n", rank_hand(FULL_HOUSE, set << 13 | pair));
+        return rank_hand(FULL_HOUSE, set << 13 | pair);
+    } else if (rank_straight(value_set) != 0) {
+        // printf("straight %d \n", rank_hand(STRAIGHT, rank_straight(value_set)));
+        return rank_hand(STRAIGHT, rank_straight(value_set));
+    } else if (count_to_value[3] != 0) {
+        uint low = keep_n(value_set ^ count_to_value[3], 2);
+        // printf("three of a kind %d \n", rank_hand(THREE_OF_A_KIND, count_to_value[3] << 13 | low));
+        return rank_hand(THREE_OF_A_KIND, count_to_value[3] << 13 | low);
+    } else if (popcount(count_to_value[2]) >= 2) {
+        uint pairs = keep_n(count_to_value[2], 2);
+        uint low = keep_highest(value_set ^ pairs);
+        // printf("two pair %d \n", rank_hand(TWO_PAIR, pairs << 13 | low));
+        return rank_hand(TWO_PAIR, pairs << 13 | low);
+    } else if (count_to_value[2] != 0) {
+        uint pair = count_to_value[2];
+        uint low = keep_n(value_set ^ pair, 3);
+        // printf("one pair %d \n", rank_hand(ONE_PAIR, pair << 13 | low));
+        return rank_hand(ONE_PAIR, pair << 13 | low);
+    } else {
+        // printf("high card %d \n", rank_hand(HIGH_CARD, keep_n(value_set, 5)));
+        return rank_hand(HIGH_CARD, keep_n(value_set, 5));
+    }
+}
+
+// Omaha hands are made of exactly two hole cards and three community cards, the best of those hands counts
+int evaluate_omaha_hand(__private const uchar* hole_cards, __private const uchar* community_cards) {
+    uchar hand[5];
+    int best_score = 0;
+
+    for (int a = 0; a < HOLE_CARDS; a++) {
+        for (int b = a + 1; b < HOLE_CARDS; b++) {
+            hand[0] = hole_cards[a];
+            hand[1] = hole_cards[b];
+            for (int c = 0; c < BOARD_CARDS; c++) {
+                for (int d = c + 1; d < BOARD_CARDS; d++) {
+                    for (int e = d + 1; e < BOARD_CARDS; e++) {
+                        hand[2] = community_cards[c];
+                        hand[3] = community_cards[d];
+                        hand[4] = community_cards[e];
+                        best_score = max(best_score, evaluate_hand(hand, 5));
+                    }
+                }
+            }
+        }
+    }
+
+    return best_score;
+}
+
+// Score of HOLE_CARDS hole cards together with all BOARD_CARDS community cards
+#ifdef RANK_TABLE
+#define FLUSH_TABLE_SIZE 8192
+// Same keys as `RANK_KEYS` in rank_table.rs, their sums are unique for every 7-card rank multiset
+__constant uint RANK_KEYS[NUM_RANKS] = {0, 1, 5, 22, 98, 453, 2031, 8698, 22854, 83661, 262349, 636345, 1479181};
+
+// Looks up a 7-card hand in the table built by `RankTable`: flushes by the ranks of the flush suit,
+// everything else by the sum of the rank keys after the flush part
+int evaluate_hand_with_table(__private const uchar* hand, __global const uint* rank_table) {
+    uint suit_ranks[NUM_SUITS] = {0, 0, 0, 0};
+    uint rank_key = 0;
+    for (int i = 0; i < 7; i++) {
+        suit_ranks[get_suit(hand[i])] |= 1 << GET_RANK(hand[i]);
+        rank_key += RANK_KEYS[GET_RANK(hand[i])];
+    }
+    for (int suit = 0; suit < NUM_SUITS; suit++) {
+        if (popcount(suit_ranks[suit]) >= 5) return rank_table[suit_ranks[suit]];
+    }
+    return rank_table[FLUSH_TABLE_SIZE + rank_key];
+}
+#endif
+
+// `rank_table` is only read when the kernel is built with RANK_TABLE
+int evaluate_full_hand(__private const uchar* hand, __global const uint* rank_table) {
+#ifdef RANK_TABLE
+    return evaluate_hand_with_table(hand, rank_table);
+#else
+    return evaluate_hand(hand, HAND_CARDS);
+#endif
+}
+
+int evaluate_player_hand(__private const uchar* hole_cards, __private const uchar* community_cards, __global const uint* rank_table) {
+#ifdef OMAHA
+    return evaluate_omaha_hand(hole_cards, community_cards);
+#else
+    uchar full_hand[HAND_CARDS];
+    copy_private_to_private(full_hand, hole_cards, HOLE_CARDS);
+    copy_private_to_private(full_hand + HOLE_CARDS, community_cards, BOARD_CARDS);
+    return evaluate_full_hand(full_hand, rank_table);
+#endif
+}
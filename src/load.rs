@@ -6,6 +6,8 @@ use bincode;
 use itertools::Itertools;
 
 use crate::encode::decode_cards;
+use crate::game_def::GameDef;
+use crate::hand_source::HandSource;
 
 fn load_data(file_path: &str) -> Result<Vec<i64>, Box<dyn std::error::Error>> {
     let file = File::open(file_path)?;
@@ -14,6 +16,14 @@ fn load_data(file_path: &str) -> Result<Vec<i64>, Box<dyn std::error::Error>> {
     Ok(hands)
 }
 
+// Decodes the hands of a batch, dropping the ones with cards outside the deck of the game variant
+fn decode_hands(encoded_hands: Vec<i64>, game_def: GameDef) -> Vec<Vec<u8>> {
+    encoded_hands.iter()
+        .map(|&encoded_cards| decode_cards(encoded_cards))
+        .filter(|hand| hand.iter().all(|&card| game_def.contains_card(card)))
+        .collect()
+}
+
 pub struct HandLoader {
     pub batch_size: usize,
     pub total_batches: usize,
@@ -21,13 +31,19 @@ pub struct HandLoader {
     pub folder_path: String,
     pub file_names: Vec<String>,
     pub round: usize,
+    pub game_def: GameDef,
     pub current_batch_hands: Vec<Vec<u8>>,
 }
 
 impl HandLoader {
-    pub fn new(round: usize) -> Result<Self, Box<dyn Error>> {
+    // Loads the batches from `CANONICAL_HANDS_FOLDER_PATH`
+    pub fn new(round: usize, game_def: GameDef) -> Result<Self, Box<dyn Error>> {
         let folder_path = std::env::var("CANONICAL_HANDS_FOLDER_PATH")?;
+        Self::from_folder(round, game_def, &folder_path)
+    }
 
+    pub fn from_folder(round: usize, game_def: GameDef, folder_path: &str) -> Result<Self, Box<dyn Error>> {
+        let folder_path = folder_path.to_string();
         let entries = fs::read_dir(&folder_path)?;
         let file_names: Vec<String> = entries.map(|entry| {
             if let Ok(entry) = entry {
@@ -62,7 +78,7 @@ impl HandLoader {
 
         if let Some(first_batch_file_name) = first_batch_file_name {
             let file_path = format!("{}/{}", &folder_path, first_batch_file_name);
-            let current_batch_hands: Vec<Vec<u8>> = load_data(&file_path)?.iter().map(|&encoded_cards| decode_cards(encoded_cards)).collect();
+            let current_batch_hands = decode_hands(load_data(&file_path)?, game_def);
 
             return Ok(Self {
                 batch_size: current_batch_hands.len(),
@@ -71,6 +87,7 @@ impl HandLoader {
                 folder_path,
                 file_names: round_filenames,
                 round,
+                game_def,
                 current_batch_hands
             })
         }
@@ -78,7 +95,7 @@ impl HandLoader {
         return Err("Failed".into());
     }
 
-    pub fn load_next_batch(&mut self) {
+    fn load_next_batch_from_file(&mut self) {
         if self.current_batch < self.total_batches-1 {
             let new_batch = self.current_batch + 1;
             let new_file_name = self.file_names.iter()
@@ -86,13 +103,22 @@ impl HandLoader {
                 .expect(format!("Could not find file for round {} batch {}", self.round, new_batch).as_str());
             println!("Loading next batch: {}", new_file_name);
             let file_path = format!("{}/{}", &self.folder_path, new_file_name);
-            let current_batch_hands: Vec<Vec<u8>> = load_data(&file_path)
-                .expect(format!("Could not load file for round {} batch {}", self.round, new_batch).as_str())
-                .iter()
-                .map(|&encoded_cards| decode_cards(encoded_cards)).collect();
+            let encoded_hands = load_data(&file_path)
+                .expect(format!("Could not load file for round {} batch {}", self.round, new_batch).as_str());
+            let current_batch_hands = decode_hands(encoded_hands, self.game_def);
 
             self.current_batch += 1;
             self.current_batch_hands = current_batch_hands;
         }
     }
 }
+
+impl HandSource for HandLoader {
+    fn round(&self) -> usize { self.round }
+    fn game_def(&self) -> GameDef { self.game_def }
+    fn total_batches(&self) -> usize { self.total_batches }
+    fn current_batch(&self) -> usize { self.current_batch }
+    fn current_batch_hands(&self) -> &Vec<Vec<u8>> { &self.current_batch_hands }
+    fn take_current_batch_hands(&mut self) -> Vec<Vec<u8>> { std::mem::take(&mut self.current_batch_hands) }
+    fn load_next_batch(&mut self) { self.load_next_batch_from_file() }
+}
@@ -1,34 +1,153 @@
+mod cards;
 mod encode;
+mod evaluate;
+mod exact_hand_strength;
+mod game_def;
+mod hand_source;
+mod isomorphism;
 mod load;
 mod logger;
+mod opponent_range;
+mod pipeline;
+mod rank_table;
+mod sampling;
+mod tuning;
+mod verify;
 mod kernel;
+#[cfg(test)]
+mod golden_tests;
+mod canonical_hands {
+    pub mod generate;
+    pub mod save;
+}
+mod feature_lookup {
+    pub mod lookup;
+    pub mod lru_cache;
+}
+mod hand_potential {
+    pub mod generate;
+    pub mod save;
+}
 mod hand_strength_histogram {
+    pub mod bin_edges;
+    pub mod cpu;
     pub mod generate;
+    pub mod load;
     pub mod save;
+    pub mod validate;
 }
 mod opponent_cluster_hand_strength {
+    pub mod cpu;
     pub mod generate;
+    pub mod load;
     pub mod save;
     pub mod load_labels;
 }
+mod potential_aware_histogram {
+    pub mod generate;
+    pub mod save;
+}
+mod toy_game {
+    pub mod generate;
+}
 mod proto {
     include!("proto/build/_.rs");
 }
 
 use dotenv::dotenv;
-use hand_strength_histogram::generate::generate_hand_strength_histograms;
-use opponent_cluster_hand_strength::generate::generate_opponent_cluster_hand_strengths;
+use canonical_hands::generate::generate_canonical_hands;
+use hand_strength_histogram::generate::{generate_hand_strength_histograms, HandStrengthHistogramSettings};
+use hand_strength_histogram::validate::{validate_hand_strength_histograms, ValidationSettings};
+use opponent_cluster_hand_strength::generate::{generate_opponent_cluster_hand_strengths, OpponentClusterHandStrengthSettings};
+use toy_game::generate::generate_toy_game_features;
 
+use crate::game_def::GameDef;
+use crate::kernel::log_available_devices;
+use crate::load::HandLoader;
 use crate::logger::init_logger;
+use crate::verify::{verify_hand_strength_histograms, verify_opponent_cluster_hand_strengths};
 
 static PATH_EXPORT: &str = "./exports";
+static PATH_CANONICAL_HANDS_EXPORT: &str = "./exports/canonical_hands";
+static CANONICAL_HANDS_BATCH_SIZE: usize = 10_000_000;
 static PATH_OPPONENT_CLUSTER_LABELS: &str = "./imports/labels_round_0_initialization_237.bin";
 
 fn main() {
     init_logger().expect("Failed to initialize logger");
     dotenv().ok();
-    
-    // generate_hand_strength_histograms(2, PATH_EXPORT);
 
-    generate_opponent_cluster_hand_strengths(3, PATH_EXPORT, PATH_OPPONENT_CLUSTER_LABELS);
+    // `cargo run --release -- validate <round> [sample_hands] [import_path]` checks exported
+    // histograms against exact hand strength and `cargo run --release -- verify <hsh|ochs> <round> [import_path]`
+    // checks every exported row, instead of generating features. `cargo run --release -- devices`
+    // lists the OpenCL devices with the indices `OPENCL_DEVICES` takes and `cargo run --release -- canonical <round> [export_path]`
+    // writes the canonical hand batches of a round. `cargo run --release -- toy <leduc|kuhn> [export_path]`
+    // exports the exact features of a toy game
+    let args: Vec<String> = std::env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("toy") => {
+            let usage = "Usage: toy <leduc|kuhn> [export_path]";
+            // Every preflop hand gets the cluster of its rank: J, J, Q, Q, K, K in Leduc and J, Q, K in Kuhn
+            let (game_def, labels_preflop, default_export_path): (GameDef, &[u32], &str) = match args.get(2).map(String::as_str) {
+                Some("leduc") => (GameDef::leduc(), &[0, 0, 1, 1, 2, 2], "./exports/leduc"),
+                Some("kuhn") => (GameDef::kuhn(), &[0, 1, 2], "./exports/kuhn"),
+                _ => panic!("{}", usage),
+            };
+            let export_path = args.get(3).map(String::as_str).unwrap_or(default_export_path);
+            generate_toy_game_features(game_def, 30, labels_preflop, export_path);
+            return;
+        },
+        Some("canonical") => {
+            let round: usize = args.get(2).and_then(|round| round.parse().ok()).expect("Usage: canonical <round> [export_path]");
+            let export_path = args.get(3).map(String::as_str).unwrap_or(PATH_CANONICAL_HANDS_EXPORT);
+            generate_canonical_hands(round, CANONICAL_HANDS_BATCH_SIZE, GameDef::holdem(), export_path);
+            return;
+        },
+        Some("devices") => {
+            log_available_devices();
+            return;
+        },
+        Some("validate") => {
+            let round: usize = args.get(2).and_then(|round| round.parse().ok()).expect("Usage: validate <round> [sample_hands] [import_path]");
+            let settings = ValidationSettings {
+                sample_hands: args.get(3).map(|sample_hands| sample_hands.parse().expect("Invalid sample_hands")).unwrap_or(100),
+                ..Default::default()
+            };
+            let import_path = args.get(4).map(String::as_str).unwrap_or(PATH_EXPORT);
+            let mut hand_source = HandLoader::new(round, GameDef::holdem()).expect("Failed to initialize HandLoader for round");
+            let report = validate_hand_strength_histograms(&mut hand_source, import_path, &settings);
+            if report.hands.iter().any(|hand| !hand.passed(&settings)) {
+                std::process::exit(1);
+            }
+            return;
+        },
+        Some("verify") => {
+            let usage = "Usage: verify <hsh|ochs> <round> [import_path]";
+            let round: usize = args.get(3).and_then(|round| round.parse().ok()).expect(usage);
+            let import_path = args.get(4).map(String::as_str).unwrap_or(PATH_EXPORT);
+            let mut hand_source = HandLoader::new(round, GameDef::holdem()).expect("Failed to initialize HandLoader for round");
+            let report = match args.get(2).map(String::as_str) {
+                Some("hsh") => verify_hand_strength_histograms(&mut hand_source, import_path),
+                Some("ochs") => verify_opponent_cluster_hand_strengths(&mut hand_source, import_path),
+                _ => panic!("{}", usage),
+            };
+            if !report.bad_rows.is_empty() {
+                std::process::exit(1);
+            }
+            return;
+        },
+        _ => {},
+    }
+
+    // let mut hand_source = HandLoader::new(2, GameDef::holdem()).expect("Failed to initialize HandLoader for round");
+    // generate_hand_strength_histograms(&mut hand_source, &HandStrengthHistogramSettings::default(), PATH_EXPORT);
+
+    // for num_opponents in 1..=5 {
+    //     let mut hand_source = HandLoader::new(2, GameDef::holdem()).expect("Failed to initialize HandLoader for round");
+    //     let settings = HandStrengthHistogramSettings { num_opponents, ..Default::default() };
+    //     generate_hand_strength_histograms(&mut hand_source, &settings, &format!("{}/opponents_{}", PATH_EXPORT, num_opponents));
+    // }
+
+    let mut hand_source = HandLoader::new(3, GameDef::holdem()).expect("Failed to initialize HandLoader");
+    let hand_source_preflop = HandLoader::new(0, GameDef::holdem()).expect("Failed to initialize preflop HandLoader for round");
+    generate_opponent_cluster_hand_strengths(&mut hand_source, &hand_source_preflop, PATH_EXPORT, PATH_OPPONENT_CLUSTER_LABELS, &OpponentClusterHandStrengthSettings::default());
 }
@@ -0,0 +1,33 @@
+use rayon::prelude::*;
+
+use crate::game_def::GameDef;
+use crate::rank_table::{evaluate_player_hand_with, RankTable};
+
+// CPU port of `simulate_poker_hands` in kernel.cl, with the kernel's layout of 8 values per hand.
+// Like the kernel, it doesn't skip cluster hands that share cards with the hand. Hands are scored
+// with `rank_table` if there is one
+pub fn simulate_opponent_cluster_hand_strengths(
+    hands: &[Vec<u8>],
+    game_def: GameDef,
+    clusters: &[Vec<&Vec<u8>>],
+    rank_table: Option<&RankTable>
+) -> Vec<i32> {
+    hands.par_iter()
+        .flat_map_iter(|hand| {
+            let (hole_cards, board) = hand.split_at(2);
+            let player_score = evaluate_player_hand_with(rank_table, hole_cards, board, game_def.evaluator);
+
+            clusters.iter()
+                .map(|cluster| {
+                    let opponents_beaten = cluster.iter()
+                        .filter(|opponent_cards| {
+                            player_score > evaluate_player_hand_with(rank_table, opponent_cards, board, game_def.evaluator)
+                        })
+                        .count();
+                    let win_probability = opponents_beaten as f32 / cluster.len() as f32;
+                    (win_probability * 255.0) as i32 // Store win probability scaled to 0-255
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
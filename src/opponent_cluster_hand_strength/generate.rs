@@ -1,19 +1,56 @@
 
+use ocl::{Buffer, Kernel};
 use ocl::builders::{BufferBuilder, KernelBuilder};
 use std::process::abort;
+use std::sync::Mutex;
 use itertools::Itertools;
 
-use crate::kernel::KernelContainer;
-use crate::load::HandLoader;
+use crate::kernel::{run_chunks_on_devices, Backend, KernelContainer};
+use crate::pipeline::run_batches_pipelined;
+use crate::tuning::{launch_settings, LaunchSettings};
+use crate::hand_source::HandSource;
+use crate::opponent_cluster_hand_strength::cpu::simulate_opponent_cluster_hand_strengths;
 use crate::opponent_cluster_hand_strength::load_labels::load_opponent_cluster_labels;
 use crate::opponent_cluster_hand_strength::save::save_opponent_cluster_hand_strengths_to_file;
+use crate::rank_table::{build_rank_table, rank_table_kernel_data};
+use crate::verify::{find_bad_rows, log_bad_rows, opponent_cluster_hand_strength_row_problem};
 
+pub struct OpponentClusterHandStrengthSettings {
+    // Look 7-card hands up in a precomputed rank table instead of evaluating them bitwise
+    pub use_rank_table: bool,
+    pub backend: Backend,
+}
+
+impl Default for OpponentClusterHandStrengthSettings {
+    fn default() -> Self {
+        Self {
+            use_rank_table: false,
+            backend: Backend::OpenCl,
+        }
+    }
+}
 
-pub fn generate_opponent_cluster_hand_strengths(round: usize, path_export: &str, path_opponent_cluster_labels: &str) {
-    let mut hand_loader = HandLoader::new(round).expect("Failed to initialize HandLoader");
-    let hand_loader_preflop = HandLoader::new(0).expect("Failed to initialize preflop HandLoader for round");
-    log::info!("Initialized HandLoader with round {} and batch 0/{}", round, hand_loader.total_batches-1);
-    let hands_preflop = &hand_loader_preflop.current_batch_hands;
+// `hand_source_preflop` has to serve the 169 preflop hands in the same order as the labels
+pub fn generate_opponent_cluster_hand_strengths(
+    hand_source: &mut dyn HandSource,
+    hand_source_preflop: &dyn HandSource,
+    path_export: &str,
+    path_opponent_cluster_labels: &str,
+    settings: &OpponentClusterHandStrengthSettings
+) {
+    let round = hand_source.round();
+    assert!(
+        hand_source.game_def().hole_cards == 2,
+        "Opponent cluster hand strength needs 2-card hands, {:?} is not supported",
+        hand_source.game_def()
+    );
+    log::info!(
+        "Generating opponent cluster hand strengths for round {} in {} batches on {:?}",
+        round,
+        hand_source.total_batches(),
+        settings.backend
+    );
+    let hands_preflop = hand_source_preflop.current_batch_hands();
     let labels_preflop = load_opponent_cluster_labels(path_opponent_cluster_labels)
         .expect("Failed to load opponent cluster labels");
 
@@ -64,126 +101,255 @@ pub fn generate_opponent_cluster_hand_strengths(round: usize, path_export: &str,
     // println!("cluster_hands.len(): {:?}", cluster_hands.len());
 
 
-    let src = include_str!("./kernel.cl");
-    let kernel_container = KernelContainer::new(src);
-    let max_work_group_size = kernel_container.device.max_wg_size().unwrap();
-    let gpu_chunk_size = max_work_group_size * 32;
-    // let gpu_chunk_size = 10;
-    log::info!("Set max group size to {}", gpu_chunk_size);
-
-    for batch_index in 0..hand_loader.total_batches {
-        let mut hands_analyzed = 0;
-        let current_batch_hands_amount = hand_loader.current_batch_hands.len();
+    let game_def = hand_source.game_def();
+    let (gpu, cpu_rank_table) = match settings.backend {
+        Backend::OpenCl => (Some(OpponentClusterHandStrengthGpu::new(hand_source, settings, &cluster_hands, &cluster_offsets, &cluster_sizes)), None),
+        Backend::Cpu { .. } => (None, build_rank_table(game_def, settings.use_rank_table)),
+    };
+    let total_batches = hand_source.total_batches();
+    run_batches_pipelined(
+        hand_source,
+        |batch_index, hands| {
+            let opponent_cluster_hand_strengths = match &gpu {
+                Some(gpu) => gpu.run_batch(batch_index, total_batches, hands),
+                None => simulate_opponent_cluster_hand_strengths(hands, game_def, &clusters, cpu_rank_table.as_ref()),
+            };
 
-        let hands = &hand_loader.current_batch_hands;
-        let mut results: Vec<Vec<u8>> = vec![];
+            // Checked before the values are narrowed to u8, which would hide values out of range
+            let rows = opponent_cluster_hand_strengths.chunks(8).collect_vec();
+            let bad_rows = find_bad_rows(batch_index, &rows, hands.len(), |row| opponent_cluster_hand_strength_row_problem(row));
+            log_bad_rows("OCHS", round, &bad_rows);
 
-        for (gpu_batch_index, chunk) in hands.chunks(gpu_chunk_size).enumerate() {
-            let hands_data_flattened = chunk.to_vec().concat();
+            // println!("hands: {:?}", hands.iter().map(|hand| hand.iter().map(|&card| card_to_string(card)).collect::<Vec<_>>()).collect::<Vec<_>>());
+            opponent_cluster_hand_strengths.chunks(8)
+                .map(|chunk| chunk.iter().map(|&value| value as u8).collect())
+                .collect_vec()
+        },
+        |batch_index, results: Vec<Vec<u8>>| {
+            save_opponent_cluster_hand_strengths_to_file(results, round, batch_index, path_export)
+                .expect(format!("ERROR: Failed to save OCHS for round {} batch #{}", round, batch_index).as_str());
+        }
+    );
+}
 
-            // Number of hands
-            let num_hands = chunk.len();
+// Devices, launch settings and kernels of the OpenCL backend
+struct OpponentClusterHandStrengthGpu {
+    round: usize,
+    kernel_containers: Vec<KernelContainer>,
+    device_launch_settings: Vec<LaunchSettings>,
+    // Each device is only used by one thread at a time, the mutex just makes that visible to the compiler
+    device_kernels: Vec<Mutex<OpponentClusterHandStrengthKernel>>,
+    gpu_chunk_size: usize,
+}
 
-            let mut opponent_cluster_hand_strengths: Vec<i32> = vec![0; num_hands * 8];
+impl OpponentClusterHandStrengthGpu {
+    fn new(
+        hand_source: &dyn HandSource,
+        settings: &OpponentClusterHandStrengthSettings,
+        cluster_hands: &[u8],
+        cluster_offsets: &[i32],
+        cluster_sizes: &[i32]
+    ) -> Self {
+        let round = hand_source.round();
+        let src = concat!(include_str!("../kernel_common.cl"), include_str!("./kernel.cl"));
+        let (rank_table, rank_table_build_options) = rank_table_kernel_data(hand_source.game_def(), settings.use_rank_table);
+        let build_options = format!("{}{}", hand_source.game_def().kernel_build_options(), rank_table_build_options);
+        let kernel_containers = KernelContainer::for_selected_devices(src, &build_options);
+        let cards_per_hand = hand_source.game_def().cards_in_round(round);
 
-            let hands_buffer = BufferBuilder::<u8>::new()
-                .flags(ocl::flags::MEM_READ_ONLY)
-                .len(hands_data_flattened.len())
-                .copy_host_slice(&hands_data_flattened)
-                .context(&kernel_container.context)
-                .build().unwrap();
+        // Tuned on the hands of the first batch
+        let kernel_key = format!("opponent_cluster_hand_strength round {} {}", round, build_options);
+        let sample_hands = hand_source.current_batch_hands();
+        let device_launch_settings = kernel_containers.iter()
+            .map(|kernel_container| launch_settings(
+                kernel_container,
+                &kernel_key,
+                |chunk_size| (
+                    OpponentClusterHandStrengthKernel::new(kernel_container, chunk_size, cards_per_hand, cluster_hands, cluster_offsets, cluster_sizes, &rank_table),
+                    sample_hands.iter().cycle().take(chunk_size).cloned().collect_vec()
+                ),
+                |(kernel, _)| &kernel.kernel,
+                |(kernel, hands), candidate| { kernel.run(kernel_container, hands, candidate); }
+            ))
+            .collect_vec();
+        // Chunks are shared between the devices, so they're sized for the smallest one
+        let gpu_chunk_size = device_launch_settings.iter().map(|launch_settings| launch_settings.chunk_size).min().unwrap();
+        // let gpu_chunk_size = 10;
+        log::info!("Set chunk size to {} on {} device(s)", gpu_chunk_size, kernel_containers.len());
 
-            let opponent_cluster_hand_strengths_buffer = BufferBuilder::<i32>::new()
-                .flags(ocl::flags::MEM_READ_WRITE)
-                .len(opponent_cluster_hand_strengths.len())
-                .context(&kernel_container.context)
-                .build().unwrap();
+        let device_kernels = kernel_containers.iter()
+            .map(|kernel_container| Mutex::new(OpponentClusterHandStrengthKernel::new(
+                kernel_container,
+                gpu_chunk_size,
+                cards_per_hand,
+                cluster_hands,
+                cluster_offsets,
+                cluster_sizes,
+                &rank_table
+            )))
+            .collect_vec();
 
-            // Make sure we clear the opponent_cluster_hand_strengths buffer since the GPU caches it appaerently
-            opponent_cluster_hand_strengths_buffer.cmd()
-                .queue(&kernel_container.queue)
-                .fill(0, None)
-                .enq()
-                .unwrap();
-
-            let cluster_hands_buffer = BufferBuilder::<u8>::new()
-                .flags(ocl::flags::MEM_READ_ONLY)
-                .len(cluster_hands.len())
-                .copy_host_slice(&cluster_hands)
-                .context(&kernel_container.context)
-                .build().unwrap();
-
-            let cluster_offsets_buffer = BufferBuilder::<i32>::new()
-                .flags(ocl::flags::MEM_READ_ONLY)
-                .len(cluster_offsets.len())
-                .copy_host_slice(&cluster_offsets)
-                .context(&kernel_container.context)
-                .build().unwrap();
-
-            let cluster_sizes_buffer = BufferBuilder::<i32>::new()
-                .flags(ocl::flags::MEM_READ_ONLY)
-                .len(cluster_sizes.len())
-                .copy_host_slice(&cluster_sizes)
-                .context(&kernel_container.context)
-                .build().unwrap();
-
-            // Setup kernel
-            let kernel = KernelBuilder::new()
-                .program(&kernel_container.program)
-                .name("simulate_poker_hands")
-                .arg(&hands_buffer)
-                .arg(&opponent_cluster_hand_strengths_buffer)
-                .arg(&cluster_hands_buffer)
-                .arg(&cluster_offsets_buffer)
-                .arg(&cluster_sizes_buffer)
-                .arg(num_hands)
-                .build()
-                .unwrap();
-
-            unsafe { 
-                kernel
-                    .cmd()
-                    .queue(&kernel_container.queue)
-                    .global_work_size(gpu_chunk_size)
-                    .enq()
-                    .unwrap();
-            }
-
-            kernel_container.queue.finish().unwrap();
+        Self {
+            round,
+            kernel_containers,
+            device_launch_settings,
+            device_kernels,
+            gpu_chunk_size,
+        }
+    }
 
-            // Read the data back into a Rust vector
-            opponent_cluster_hand_strengths_buffer.cmd()
-                .queue(&kernel_container.queue)
-                .read(&mut opponent_cluster_hand_strengths)
-                .enq()
-                .unwrap();
+    // Opponent cluster hand strengths of a batch, 8 per hand, its chunks spread over the devices
+    fn run_batch(&self, batch_index: usize, total_batches: usize, hands: &[Vec<u8>]) -> Vec<i32> {
+        let chunks = hands.chunks(self.gpu_chunk_size).collect_vec();
 
-            
-            let opponent_cluster_hand_strengths_unflattened = opponent_cluster_hand_strengths.chunks(8)
-                .map(|chunk| chunk.iter().map(|&value| value as u8).collect())
-                .collect_vec();
-            // println!("hands: {:?}", chunk.iter().map(|hand| hand.iter().map(|&card| card_to_string(card)).collect::<Vec<_>>()).collect::<Vec<_>>());
-            // println!("opponent_cluster_hand_strengths_unflattened: {:?}", opponent_cluster_hand_strengths_unflattened);
+        let chunk_results = run_chunks_on_devices(self.kernel_containers.len(), &chunks, |device_index, gpu_batch_index, chunk| {
+            let device_kernel = self.device_kernels[device_index].lock().unwrap();
+            let opponent_cluster_hand_strengths = device_kernel.run(
+                &self.kernel_containers[device_index],
+                chunk,
+                self.device_launch_settings[device_index]
+            );
 
-            hands_analyzed += opponent_cluster_hand_strengths_unflattened.len();
             if gpu_batch_index > 0 && gpu_batch_index % 1000 == 0 {
                 log::info!(
-                    "Finished GPU batch. Round {}, batch {}/{} gpu batch {} hands {}/{} in batch",
-                    round,
+                    "Finished GPU batch. Round {}, batch {}/{} gpu batch {}/{} on device {}",
+                    self.round,
                     batch_index,
-                    hand_loader.total_batches-1,
+                    total_batches-1,
                     gpu_batch_index,
-                    hands_analyzed,
-                    current_batch_hands_amount
+                    chunks.len()-1,
+                    device_index
                 );
             }
 
-            results.extend(opponent_cluster_hand_strengths_unflattened);
+            opponent_cluster_hand_strengths
+        });
+
+        chunk_results.concat()
+    }
+}
+
+// The kernel of one device with its buffers. The clusters and the rank table are uploaded once and
+// the per-chunk buffers are sized for a full chunk, so running a chunk only writes the hands and
+// sets the `num_hands` argument
+struct OpponentClusterHandStrengthKernel {
+    kernel: Kernel,
+    hands_buffer: Buffer<u8>,
+    opponent_cluster_hand_strengths_buffer: Buffer<i32>,
+    _constant_buffers: (Buffer<u8>, Buffer<i32>, Buffer<i32>, Buffer<u32>),
+}
+
+impl OpponentClusterHandStrengthKernel {
+    fn new(
+        kernel_container: &KernelContainer,
+        gpu_chunk_size: usize,
+        cards_per_hand: usize,
+        cluster_hands: &[u8],
+        cluster_offsets: &[i32],
+        cluster_sizes: &[i32],
+        rank_table: &[u32]
+    ) -> Self {
+        let hands_buffer = BufferBuilder::<u8>::new()
+            .flags(ocl::flags::MEM_READ_ONLY)
+            .len(gpu_chunk_size * cards_per_hand)
+            .context(&kernel_container.context)
+            .build().unwrap();
+
+        let opponent_cluster_hand_strengths_buffer = BufferBuilder::<i32>::new()
+            .flags(ocl::flags::MEM_READ_WRITE)
+            .len(gpu_chunk_size * 8)
+            .context(&kernel_container.context)
+            .build().unwrap();
+
+        let cluster_hands_buffer = BufferBuilder::<u8>::new()
+            .flags(ocl::flags::MEM_READ_ONLY)
+            .len(cluster_hands.len())
+            .copy_host_slice(cluster_hands)
+            .context(&kernel_container.context)
+            .build().unwrap();
+
+        let cluster_offsets_buffer = BufferBuilder::<i32>::new()
+            .flags(ocl::flags::MEM_READ_ONLY)
+            .len(cluster_offsets.len())
+            .copy_host_slice(cluster_offsets)
+            .context(&kernel_container.context)
+            .build().unwrap();
+
+        let cluster_sizes_buffer = BufferBuilder::<i32>::new()
+            .flags(ocl::flags::MEM_READ_ONLY)
+            .len(cluster_sizes.len())
+            .copy_host_slice(cluster_sizes)
+            .context(&kernel_container.context)
+            .build().unwrap();
+
+        let rank_table_buffer = BufferBuilder::<u32>::new()
+            .flags(ocl::flags::MEM_READ_ONLY)
+            .len(rank_table.len())
+            .copy_host_slice(rank_table)
+            .context(&kernel_container.context)
+            .build().unwrap();
+
+        let kernel = KernelBuilder::new()
+            .program(&kernel_container.program)
+            .name("simulate_poker_hands")
+            .arg(&hands_buffer)
+            .arg(&opponent_cluster_hand_strengths_buffer)
+            .arg(&cluster_hands_buffer)
+            .arg(&cluster_offsets_buffer)
+            .arg(&cluster_sizes_buffer)
+            .arg(&rank_table_buffer)
+            .arg_named("num_hands", 0u32)
+            .build()
+            .unwrap();
+
+        Self {
+            kernel,
+            hands_buffer,
+            opponent_cluster_hand_strengths_buffer,
+            _constant_buffers: (cluster_hands_buffer, cluster_offsets_buffer, cluster_sizes_buffer, rank_table_buffer),
+        }
+    }
+    // Opponent cluster hand strengths of every hand in `hands`, at most one full chunk
+    fn run(&self, kernel_container: &KernelContainer, hands: &[Vec<u8>], launch_settings: LaunchSettings) -> Vec<i32> {
+        let hands_data_flattened = hands.concat();
+
+        // Number of hands
+        let num_hands = hands.len();
+
+        let mut opponent_cluster_hand_strengths: Vec<i32> = vec![0; num_hands * 8];
+
+        // The last chunk of a batch only fills the start of the buffers
+        self.hands_buffer.cmd().queue(&kernel_container.queue).write(&hands_data_flattened).enq().unwrap();
+
+        // Make sure we clear the opponent_cluster_hand_strengths buffer since the GPU caches it appaerently
+        self.opponent_cluster_hand_strengths_buffer.cmd()
+            .queue(&kernel_container.queue)
+            .fill(0, None)
+            .enq()
+            .unwrap();
+
+        self.kernel.set_arg("num_hands", num_hands as u32).unwrap();
+
+        unsafe { 
+            let mut kernel_cmd = self.kernel
+                .cmd()
+                .queue(&kernel_container.queue)
+                .global_work_size(launch_settings.global_work_size(num_hands));
+            if let Some(local_size) = launch_settings.local_size {
+                kernel_cmd = kernel_cmd.local_work_size(local_size);
+            }
+            kernel_cmd.enq().unwrap();
         }
 
-        save_opponent_cluster_hand_strengths_to_file(results, round, batch_index, path_export)
-            .expect(format!("ERROR: Failed to save OCHS for round {} batch #{}", round, batch_index).as_str());
+        kernel_container.queue.finish().unwrap();
+
+        // Read the data back into a Rust vector
+        self.opponent_cluster_hand_strengths_buffer.cmd()
+            .queue(&kernel_container.queue)
+            .read(&mut opponent_cluster_hand_strengths)
+            .enq()
+            .unwrap();
 
-        hand_loader.load_next_batch();
+        opponent_cluster_hand_strengths
     }
 }
@@ -0,0 +1,17 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use prost::Message;
+
+use crate::proto::OpponentClusterHandStrengthHistograms;
+
+pub fn load_opponent_cluster_hand_strengths_from_file(round: usize, batch: usize, import_path: &str) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+    let filepath = format!("{}/round_{}_batch_{}.bin", import_path, round, batch);
+
+    let mut file = BufReader::new(File::open(filepath)?);
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf)?;
+
+    let data = OpponentClusterHandStrengthHistograms::decode(&*buf)?;
+    Ok(data.data)
+}
@@ -0,0 +1,46 @@
+use std::error::Error;
+use itertools::Itertools;
+
+use crate::game_def::GameDef;
+use crate::isomorphism::{canonical_hand_index, create_hand_indexer};
+
+// Weight of every opponent hand. Stored as a 52x52 matrix indexed by `card_a * 52 + card_b`
+// (both orders hold the same weight) so the kernels can look combos up directly
+#[derive(Clone)]
+pub struct OpponentRange {
+    pub weights: Vec<f32>,
+}
+
+impl OpponentRange {
+    pub fn uniform() -> Self {
+        Self { weights: vec![1.0; 52 * 52] }
+    }
+
+    // One weight per combo, combos ordered as (0, 1), (0, 2), .., (50, 51)
+    pub fn from_combo_weights(combo_weights: &[f32]) -> Result<Self, Box<dyn Error>> {
+        if combo_weights.len() != 1326 {
+            return Err(format!("Expected 1326 combo weights, got {}", combo_weights.len()).into());
+        }
+
+        let mut weights = vec![0.0; 52 * 52];
+        for (combo, &weight) in (0..52usize).tuple_combinations::<(usize, usize)>().zip(combo_weights) {
+            weights[combo.0 * 52 + combo.1] = weight;
+            weights[combo.1 * 52 + combo.0] = weight;
+        }
+        Ok(Self { weights })
+    }
+
+    // One weight per canonical preflop hand, in canonical index order (the order of the 169
+    // round 0 canonical hands), expanded to every combo of that class
+    pub fn from_canonical_class_weights(class_weights: &[f32]) -> Result<Self, Box<dyn Error>> {
+        if class_weights.len() != 169 {
+            return Err(format!("Expected 169 canonical class weights, got {}", class_weights.len()).into());
+        }
+
+        let hand_indexer = create_hand_indexer(0, GameDef::holdem());
+        let combo_weights = (0..52u8).tuple_combinations::<(u8, u8)>()
+            .map(|(card_a, card_b)| class_weights[canonical_hand_index(&hand_indexer, &[card_a, card_b]) as usize])
+            .collect_vec();
+        Self::from_combo_weights(&combo_weights)
+    }
+}
@@ -0,0 +1,117 @@
+use std::sync::mpsc::sync_channel;
+
+use crate::hand_source::HandSource;
+
+// Runs every batch of `hand_source` through `compute` and hands the results to `save`, with the
+// three stages on their own threads. The channels between them hold one batch each, so the next
+// batch is decoded and the previous results are written while the current batch is on the device,
+// without keeping more than a few batches in memory
+pub fn run_batches_pipelined<R: Send>(
+    hand_source: &mut dyn HandSource,
+    mut compute: impl FnMut(usize, &[Vec<u8>]) -> R,
+    save: impl Fn(usize, R) + Send
+) {
+    let total_batches = hand_source.total_batches();
+
+    std::thread::scope(|scope| {
+        let (batch_sender, batch_receiver) = sync_channel::<(usize, Vec<Vec<u8>>)>(1);
+        let (result_sender, result_receiver) = sync_channel::<(usize, R)>(1);
+
+        scope.spawn(move || {
+            for batch_index in 0..total_batches {
+                let hands = hand_source.take_current_batch_hands();
+                if batch_sender.send((batch_index, hands)).is_err() {
+                    return;
+                }
+                if batch_index < total_batches - 1 {
+                    hand_source.load_next_batch();
+                }
+            }
+        });
+
+        scope.spawn(move || {
+            for (batch_index, results) in result_receiver {
+                save(batch_index, results);
+            }
+        });
+
+        for (batch_index, hands) in batch_receiver {
+            let results = compute(batch_index, &hands);
+            result_sender.send((batch_index, results)).expect("Saving thread stopped early");
+        }
+        drop(result_sender);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::game_def::GameDef;
+
+    // Batch b holds the single hand [b], and every loaded batch is counted
+    struct CountingHandSource {
+        total_batches: usize,
+        current_batch: usize,
+        current_batch_hands: Vec<Vec<u8>>,
+        batches_loaded: usize,
+    }
+
+    impl CountingHandSource {
+        fn new(total_batches: usize) -> Self {
+            Self { total_batches, current_batch: 0, current_batch_hands: vec![vec![0]], batches_loaded: 1 }
+        }
+    }
+
+    impl HandSource for CountingHandSource {
+        fn round(&self) -> usize { 0 }
+        fn game_def(&self) -> GameDef { GameDef::holdem() }
+        fn total_batches(&self) -> usize { self.total_batches }
+        fn current_batch(&self) -> usize { self.current_batch }
+        fn current_batch_hands(&self) -> &Vec<Vec<u8>> { &self.current_batch_hands }
+        fn take_current_batch_hands(&mut self) -> Vec<Vec<u8>> { std::mem::take(&mut self.current_batch_hands) }
+
+        fn load_next_batch(&mut self) {
+            assert!(self.current_batch + 1 < self.total_batches, "Loaded past the last batch");
+            self.current_batch += 1;
+            self.current_batch_hands = vec![vec![self.current_batch as u8]];
+            self.batches_loaded += 1;
+        }
+    }
+
+    fn run(total_batches: usize) -> (Vec<(usize, Vec<Vec<u8>>)>, Vec<(usize, usize)>, usize) {
+        let mut hand_source = CountingHandSource::new(total_batches);
+        let mut computed = vec![];
+        let saved = Mutex::new(vec![]);
+        run_batches_pipelined(
+            &mut hand_source,
+            |batch_index, hands| {
+                computed.push((batch_index, hands.to_vec()));
+                batch_index * 10
+            },
+            |batch_index, result| saved.lock().unwrap().push((batch_index, result))
+        );
+        (computed, saved.into_inner().unwrap(), hand_source.batches_loaded)
+    }
+
+    #[test]
+    fn every_batch_is_computed_and_saved_once_in_order() {
+        let (computed, saved, batches_loaded) = run(5);
+        assert_eq!(computed, (0..5).map(|batch| (batch, vec![vec![batch as u8]])).collect::<Vec<_>>());
+        assert_eq!(saved, (0..5).map(|batch| (batch, batch * 10)).collect::<Vec<_>>());
+        assert_eq!(batches_loaded, 5);
+
+        let (computed, saved, _) = run(1);
+        assert_eq!(computed, vec![(0, vec![vec![0]])]);
+        assert_eq!(saved, vec![(0, 0)]);
+    }
+
+    #[test]
+    fn source_without_batches_computes_nothing() {
+        let (computed, saved, batches_loaded) = run(0);
+        assert!(computed.is_empty());
+        assert!(saved.is_empty());
+        assert_eq!(batches_loaded, 1);
+    }
+}
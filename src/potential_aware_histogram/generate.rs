@@ -0,0 +1,86 @@
+use std::error::Error;
+use itertools::Itertools;
+use rayon::prelude::*;
+
+use crate::game_def::GameDef;
+use crate::hand_source::HandSource;
+use crate::isomorphism::{canonical_hand_count, canonical_hand_index, create_hand_indexer};
+use crate::opponent_cluster_hand_strength::load_labels::load_opponent_cluster_labels;
+use crate::potential_aware_histogram::save::save_potential_aware_histograms_to_file;
+
+// For every hand of round r, deals every possible next street and builds a histogram over the
+// round r+1 clusters of the resulting hands. `path_next_round_labels` holds the round r+1
+// `ClusteredDataLabels`, indexed by canonical hand index. Fails before generating anything if the
+// labels can't be loaded or don't have one label per round r+1 canonical hand.
+pub fn generate_potential_aware_histograms(hand_source: &mut dyn HandSource, export_path: &str, path_next_round_labels: &str) -> Result<(), Box<dyn Error>> {
+    let round = hand_source.round();
+    assert!(round < 3, "Potential-aware histograms need a next round, round {} is the last one", round);
+    assert!(
+        hand_source.game_def() == GameDef::holdem(),
+        "Potential-aware histograms need next round labels indexed by hold'em canonical hand index"
+    );
+    let next_round = round + 1;
+
+    let labels_next_round = load_opponent_cluster_labels(path_next_round_labels)?;
+    let next_round_hands = canonical_hand_count(&create_hand_indexer(next_round, GameDef::holdem()), next_round);
+    if labels_next_round.len() as u64 != next_round_hands {
+        return Err(format!(
+            "{} has {} labels, round {} has {} canonical hands",
+            path_next_round_labels,
+            labels_next_round.len(),
+            next_round,
+            next_round_hands
+        ).into());
+    }
+    let num_clusters = labels_next_round.iter().max().map(|&label| label as usize + 1).unwrap_or(0);
+    let cards_to_deal = GameDef::holdem().board_cards_per_street[next_round];
+
+    log::info!(
+        "Generating potential-aware histograms for round {} over {} round {} clusters in {} batches",
+        round,
+        num_clusters,
+        next_round,
+        hand_source.total_batches()
+    );
+
+    for batch_index in 0..hand_source.total_batches() {
+        let hands = hand_source.current_batch_hands();
+
+        let results: Vec<Vec<u8>> = hands.par_iter()
+            .map_init(
+                || create_hand_indexer(next_round, GameDef::holdem()),
+                |hand_indexer, hand| {
+                    let mut cluster_counts = vec![0u32; num_clusters];
+                    let mut total = 0;
+
+                    let remaining_cards = (0..52u8).filter(|card| !hand.contains(card));
+                    for next_cards in remaining_cards.combinations(cards_to_deal) {
+                        let next_hand = [hand.as_slice(), next_cards.as_slice()].concat();
+                        let index = canonical_hand_index(hand_indexer, &next_hand) as usize;
+                        cluster_counts[labels_next_round[index] as usize] += 1;
+                        total += 1;
+                    }
+
+                    cluster_counts.iter()
+                        .map(|&count| ((count as f32 / total as f32) * 100.0) as u8)
+                        .collect()
+                }
+            )
+            .collect();
+
+        log::info!(
+            "Finished batch. Round {}, batch {}/{} hands {}",
+            round,
+            batch_index,
+            hand_source.total_batches()-1,
+            results.len()
+        );
+
+        save_potential_aware_histograms_to_file(results, round, batch_index, export_path)
+            .expect(format!("ERROR: Failed to save potential-aware histograms for round {} batch #{}", round, batch_index).as_str());
+
+        hand_source.load_next_batch();
+    }
+
+    Ok(())
+}
@@ -0,0 +1,20 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use prost::Message;
+
+use crate::proto::PotentialAwareHistograms;
+
+pub fn save_potential_aware_histograms_to_file(potential_aware_histograms: Vec<Vec<u8>>, round: usize, batch: usize, export_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let filepath = format!("{}/round_{}_batch_{}.bin", export_path, round, batch);
+
+    let data = PotentialAwareHistograms {
+        data: potential_aware_histograms,
+    };
+
+    let mut buf = Vec::new();
+    data.encode(&mut buf)?;
+
+    let mut file = BufWriter::new(File::create(filepath)?);
+    file.write_all(&buf)?;
+    Ok(())
+}
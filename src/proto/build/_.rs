@@ -7,9 +7,33 @@ pub struct ClusteredDataLabels {
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ExpectedHandStrengths {
+    #[prost(float, repeated, tag = "1")]
+    pub ehs: ::prost::alloc::vec::Vec<f32>,
+    #[prost(float, repeated, tag = "2")]
+    pub ehs_squared: ::prost::alloc::vec::Vec<f32>,
+    #[prost(float, repeated, tag = "3")]
+    pub variance: ::prost::alloc::vec::Vec<f32>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct HandPotentials {
+    #[prost(float, repeated, tag = "1")]
+    pub ppot: ::prost::alloc::vec::Vec<f32>,
+    #[prost(float, repeated, tag = "2")]
+    pub npot: ::prost::alloc::vec::Vec<f32>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
 pub struct HandStrengthHistograms {
     #[prost(bytes = "vec", repeated, tag = "1")]
     pub data: ::prost::alloc::vec::Vec<::prost::alloc::vec::Vec<u8>>,
+    /// NUM_BINS + 1 ascending edges from 0 to 1
+    #[prost(float, repeated, tag = "2")]
+    pub bin_edges: ::prost::alloc::vec::Vec<f32>,
+    /// Trials run for every hand, in the order of `data`
+    #[prost(uint32, repeated, tag = "3")]
+    pub trials: ::prost::alloc::vec::Vec<u32>,
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -17,3 +41,9 @@ pub struct OpponentClusterHandStrengthHistograms {
     #[prost(bytes = "vec", repeated, tag = "1")]
     pub data: ::prost::alloc::vec::Vec<::prost::alloc::vec::Vec<u8>>,
 }
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PotentialAwareHistograms {
+    #[prost(bytes = "vec", repeated, tag = "1")]
+    pub data: ::prost::alloc::vec::Vec<::prost::alloc::vec::Vec<u8>>,
+}
@@ -0,0 +1,141 @@
+use itertools::Itertools;
+
+use crate::evaluate::{evaluate_hand, evaluate_player_hand};
+use crate::game_def::{Evaluator, GameDef};
+
+// Per-rank keys whose sums are unique for every 7-card rank multiset (at most 4 cards per rank),
+// so the sum of a hand's keys indexes its score in the non-flush table
+pub static RANK_KEYS: [u32; 13] = [0, 1, 5, 22, 98, 453, 2031, 8698, 22854, 83661, 262349, 636345, 1479181];
+pub const FLUSH_TABLE_SIZE: usize = 1 << 13;
+
+// Precomputed scores of every 7-card hand, built once on the host with `evaluate_hand` so that
+// table lookups and the bitwise evaluator always agree. The flush part is indexed by the ranks of
+// the flush suit, the rest by the sum of the rank keys. `data` holds both parts back to back, which
+// is the layout the kernels expect.
+pub struct RankTable {
+    pub evaluator: Evaluator,
+    pub data: Vec<u32>,
+}
+
+impl RankTable {
+    pub fn new(evaluator: Evaluator) -> Self {
+        let max_key = RANK_KEYS[12] * 4 + RANK_KEYS[11] * 3;
+        let mut data = vec![0u32; FLUSH_TABLE_SIZE + max_key as usize + 1];
+
+        // 5 up to 7 cards of one suit, the other cards can't change a flush's score
+        for (suit_ranks, score) in data.iter_mut().enumerate().take(FLUSH_TABLE_SIZE) {
+            if (suit_ranks as u32).count_ones() < 5 || (suit_ranks as u32).count_ones() > 7 {
+                continue;
+            }
+            let hand = (0..13u8).filter(|rank| suit_ranks & (1 << rank) != 0).map(|rank| rank << 2).collect_vec();
+            *score = evaluate_hand(&hand, evaluator);
+        }
+
+        // Every rank multiset, suits dealt round-robin so no suit gets more than 2 cards
+        for ranks in (0..13u8).combinations_with_replacement(7) {
+            if ranks.iter().dedup_with_count().any(|(count, _)| count > 4) {
+                continue;
+            }
+            let hand = ranks.iter().enumerate().map(|(i, &rank)| rank << 2 | (i % 4) as u8).collect_vec();
+            data[FLUSH_TABLE_SIZE + rank_key(&hand) as usize] = evaluate_hand(&hand, evaluator);
+        }
+
+        Self { evaluator, data }
+    }
+
+    // Score of a hand, same as `evaluate_hand`. Only 7-card hands are looked up
+    pub fn evaluate(&self, hand: &[u8]) -> u32 {
+        if hand.len() != 7 {
+            return evaluate_hand(hand, self.evaluator);
+        }
+
+        let mut suit_ranks = [0usize; 4];
+        for &card in hand {
+            suit_ranks[(card & 3) as usize] |= 1 << (card >> 2);
+        }
+        if let Some(&flush_ranks) = suit_ranks.iter().find(|ranks| ranks.count_ones() >= 5) {
+            return self.data[flush_ranks];
+        }
+        self.data[FLUSH_TABLE_SIZE + rank_key(hand) as usize]
+    }
+}
+
+fn rank_key(hand: &[u8]) -> u32 {
+    hand.iter().map(|&card| RANK_KEYS[(card >> 2) as usize]).sum()
+}
+
+// Score of a player's hand like `evaluate_player_hand`, looked up in `rank_table` when there is
+// one. This is what the CPU ports use, to score hands the same way as the kernels
+pub fn evaluate_player_hand_with(rank_table: Option<&RankTable>, hole_cards: &[u8], community_cards: &[u8], evaluator: Evaluator) -> u32 {
+    match rank_table {
+        Some(rank_table) => rank_table.evaluate(&[hole_cards, community_cards].concat()),
+        None => evaluate_player_hand(hole_cards, community_cards, evaluator),
+    }
+}
+
+// The rank table for `game_def` if `use_rank_table` is set
+pub fn build_rank_table(game_def: GameDef, use_rank_table: bool) -> Option<RankTable> {
+    if !use_rank_table {
+        return None;
+    }
+    assert!(
+        game_def.hole_cards + game_def.board_cards() == 7 && game_def.evaluator != Evaluator::Omaha,
+        "The rank table only covers 7-card hands, {:?} is not supported",
+        game_def
+    );
+    log::info!("Building rank table for {:?}", game_def.evaluator);
+    Some(RankTable::new(game_def.evaluator))
+}
+
+// Table data and extra build options for the kernels. Without the table the kernels still take a
+// `rank_table` argument, so they get a single unused entry
+pub fn rank_table_kernel_data(game_def: GameDef, use_rank_table: bool) -> (Vec<u32>, &'static str) {
+    match build_rank_table(game_def, use_rank_table) {
+        Some(rank_table) => (rank_table.data, " -D RANK_TABLE"),
+        None => (vec![0], ""),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::cards::cards_from_string;
+
+    // Flushes of 5 up to 7 suited cards, straight flushes next to bigger flushes, quads next to
+    // trips, full houses from two trips and wheels of both decks
+    static EDGE_CASES: [&str; 12] = [
+        "AsKsQsJs9s8s7s",
+        "AsKsQsJs9s8sAh",
+        "AsKsQsJs9sAhAd",
+        "2s3s4s5s6s7s8s",
+        "As2s3s4s5sKsQs",
+        "6s7s8s9sAsKhKd",
+        "AhAsAdAcKsKhKd",
+        "AhAsKhKsKdQhQs",
+        "2h2s3h3s4h4s5h",
+        "As2h3d4c5sKhQd",
+        "As6h7d8c9sKhQd",
+        "6s7h8d9cTsJhQd",
+    ];
+
+    #[test]
+    fn rank_table_matches_evaluator() {
+        for game_def in [GameDef::holdem(), GameDef::short_deck()] {
+            let rank_table = RankTable::new(game_def.evaluator);
+            for hand in EDGE_CASES.iter().map(|hand| cards_from_string(hand).unwrap()) {
+                assert_eq!(rank_table.evaluate(&hand), evaluate_hand(&hand, game_def.evaluator), "{:?} {:?}", game_def.evaluator, hand);
+            }
+
+            let mut rng = StdRng::seed_from_u64(3);
+            let mut deck = game_def.deck_cards();
+            for _ in 0..20_000 {
+                let (hand, _) = deck.partial_shuffle(&mut rng, 7);
+                assert_eq!(rank_table.evaluate(hand), evaluate_hand(hand, game_def.evaluator), "{:?} {:?}", game_def.evaluator, hand);
+            }
+        }
+    }
+}
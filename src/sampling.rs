@@ -0,0 +1,123 @@
+// CPU mirror of the board sampling in kernel_common.cl. It draws the same cards as the kernels for
+// the same seed, so the sampling can be tested and reproduced without a GPU
+
+pub fn xorshift(state: &mut u32) -> u32 {
+    *state ^= *state << 13;
+    *state ^= *state >> 17;
+    *state ^= *state << 5;
+    *state
+}
+
+pub fn hash_uint(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846ca68b);
+    x ^= x >> 16;
+    x
+}
+
+pub fn trial_seed(seed: u32, hand_id: u32, trial: u32) -> u32 {
+    let state = hash_uint(seed ^ hash_uint(hand_id ^ hash_uint(trial)));
+    if state == 0 { 0x9e3779b9 } else { state }
+}
+
+// Uniform in [0, bound) without modulo bias (Lemire's multiply-shift)
+pub fn bounded_random(bound: u32, state: &mut u32) -> u32 {
+    let mut x = xorshift(state);
+    let mut low = x.wrapping_mul(bound);
+    if low < bound {
+        let threshold = bound.wrapping_neg() % bound;
+        while low < threshold {
+            x = xorshift(state);
+            low = x.wrapping_mul(bound);
+        }
+    }
+    ((x as u64 * bound as u64) >> 32) as u32
+}
+
+// One step of a partial Fisher-Yates shuffle over the first `remaining_count` cards
+pub fn draw_random_card(remaining_cards: &mut [u8], remaining_count: &mut usize, state: &mut u32) -> u8 {
+    let index = bounded_random(*remaining_count as u32, state) as usize;
+    let card = remaining_cards[index];
+    *remaining_count -= 1;
+    remaining_cards[index] = remaining_cards[*remaining_count];
+    card
+}
+
+// The cards drawn to complete the board after `known_cards`, in the order the kernels draw them
+pub fn draw_runout(deck: &[u8], known_cards: &[u8], runout_size: usize, state: &mut u32) -> Vec<u8> {
+    let mut remaining_cards: Vec<u8> = deck.iter().cloned().filter(|card| !known_cards.contains(card)).collect();
+    let mut remaining_count = remaining_cards.len();
+    (0..runout_size)
+        .map(|_| draw_random_card(&mut remaining_cards, &mut remaining_count, state))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use itertools::Itertools;
+
+    use super::*;
+
+    fn chi_squared(counts: impl Iterator<Item = usize>, expected: f64) -> f64 {
+        counts.map(|count| (count as f64 - expected).powi(2) / expected).sum()
+    }
+
+    #[test]
+    fn bounded_random_stays_in_bounds() {
+        let mut state = trial_seed(7, 0, 0);
+        for bound in 1..=52 {
+            for _ in 0..1000 {
+                assert!(bounded_random(bound, &mut state) < bound);
+            }
+        }
+    }
+
+    // Every turn and river pair after a flop is equally likely: 1081 pairs, 1000 draws each on
+    // average, checked against the chi-squared critical value for p = 0.001 at 1080 degrees of freedom
+    #[test]
+    fn turn_and_river_are_uniform() {
+        let deck = (0..52).collect_vec();
+        let known_cards = [0, 13, 26, 39, 51];
+        let draws = 1_081_000;
+
+        let mut counts: HashMap<(u8, u8), usize> = HashMap::new();
+        for trial in 0..draws {
+            let mut state = trial_seed(1234, 0, trial);
+            let runout = draw_runout(&deck, &known_cards, 2, &mut state);
+            *counts.entry((runout[0].min(runout[1]), runout[0].max(runout[1]))).or_default() += 1;
+        }
+
+        assert_eq!(counts.len(), 1081);
+        let statistic = chi_squared(counts.into_values(), draws as f64 / 1081.0);
+        assert!(statistic < 1229.0, "Turn and river pairs are not uniform, chi-squared {}", statistic);
+    }
+
+    // Every card shows up equally often on preflop boards, in every board position
+    #[test]
+    fn board_cards_are_uniform_per_position() {
+        let deck = (0..52).collect_vec();
+        let known_cards = [12, 25];
+        let draws = 250_000;
+
+        let mut counts = vec![[0usize; 52]; 5];
+        for trial in 0..draws {
+            let mut state = trial_seed(99, trial, 0);
+            for (position, card) in draw_runout(&deck, &known_cards, 5, &mut state).into_iter().enumerate() {
+                counts[position][card as usize] += 1;
+            }
+        }
+
+        for position_counts in counts {
+            assert_eq!(position_counts[12] + position_counts[25], 0);
+            let remaining_counts = position_counts.iter().enumerate()
+                .filter(|(card, _)| !known_cards.contains(&(*card as u8)))
+                .map(|(_, &count)| count);
+            // Critical value for p = 0.001 at 49 degrees of freedom
+            let statistic = chi_squared(remaining_counts, draws as f64 / 50.0);
+            assert!(statistic < 85.35, "Board cards are not uniform, chi-squared {}", statistic);
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use std::fs;
+use itertools::Itertools;
+
+use crate::evaluate::evaluate_player_hand;
+use crate::exact_hand_strength::{exact_hand_strengths, hand_strength_histogram};
+use crate::game_def::GameDef;
+use crate::hand_strength_histogram::bin_edges::uniform_bin_edges;
+use crate::hand_strength_histogram::save::save_hand_strength_histograms_to_file;
+use crate::opponent_cluster_hand_strength::save::save_opponent_cluster_hand_strengths_to_file;
+
+// Every hand of a round: private cards followed by the board, without isomorphism since toy
+// games are small enough to list every hand
+pub fn enumerate_hands(game_def: GameDef, round: usize) -> Vec<Vec<u8>> {
+    let deck = game_def.deck_cards();
+    let board_cards = game_def.cards_in_round(round) - game_def.hole_cards;
+
+    deck.iter()
+        .cloned()
+        .combinations(game_def.hole_cards)
+        .flat_map(|hole_cards| {
+            let remaining_cards = deck.iter().cloned().filter(|card| !hole_cards.contains(card)).collect_vec();
+            remaining_cards.into_iter()
+                .combinations(board_cards)
+                .map(|board| [hole_cards.as_slice(), board.as_slice()].concat())
+                .collect_vec()
+        })
+        .collect()
+}
+
+// Win rate (scaled to 0-255, like the kernel) of a last round hand against every cluster of
+// private hands. Opponent hands that share a card with the hand are skipped
+pub fn opponent_cluster_hand_strengths(hand: &[u8], clusters: &[Vec<Vec<u8>>], game_def: GameDef) -> Vec<u8> {
+    let (hole_cards, board) = hand.split_at(game_def.hole_cards);
+    let player_score = evaluate_player_hand(hole_cards, board, game_def.evaluator);
+
+    clusters.iter()
+        .map(|cluster| {
+            let opponents = cluster.iter()
+                .filter(|opponent_cards| opponent_cards.iter().all(|card| !hand.contains(card)))
+                .collect_vec();
+            let opponents_beaten = opponents.iter()
+                .filter(|opponent_cards| player_score > evaluate_player_hand(opponent_cards, board, game_def.evaluator))
+                .count();
+
+            if opponents.is_empty() {
+                return 0;
+            }
+            ((opponents_beaten as f32 / opponents.len() as f32) * 255.0) as u8
+        })
+        .collect()
+}
+
+// Exact hand strength histograms for every round and opponent cluster hand strengths for the last
+// round of a toy game like Leduc or Kuhn, in the same export formats as the GPU generators. Every
+// round is a single batch. `labels_preflop` assigns a cluster to each preflop hand, in the order
+// of `enumerate_hands(game_def, 0)`
+pub fn generate_toy_game_features(game_def: GameDef, num_bins: usize, labels_preflop: &[u32], export_path: &str) {
+    let export_path_hand_strength_histograms = format!("{}/hand_strength_histograms", export_path);
+    let export_path_opponent_cluster_hand_strengths = format!("{}/opponent_cluster_hand_strengths", export_path);
+    fs::create_dir_all(&export_path_hand_strength_histograms).expect("Failed to create hand strength histograms export folder");
+    fs::create_dir_all(&export_path_opponent_cluster_hand_strengths).expect("Failed to create opponent cluster hand strengths export folder");
+    let bin_edges = uniform_bin_edges(num_bins);
+
+    for round in 0..game_def.rounds() {
+        // Every runout is enumerated, so a hand's trials are its number of runouts
+        let (histograms, trials): (Vec<Vec<u8>>, Vec<u32>) = enumerate_hands(game_def, round).iter()
+            .map(|hand| {
+                let hand_strengths = exact_hand_strengths(hand, game_def);
+                (hand_strength_histogram(&hand_strengths, &bin_edges), hand_strengths.len() as u32)
+            })
+            .unzip();
+        log::info!("Finished toy game hand strength histograms. Round {} hands {}", round, histograms.len());
+
+        save_hand_strength_histograms_to_file(histograms, &bin_edges, trials, round, 0, &export_path_hand_strength_histograms)
+            .expect(format!("ERROR: Failed to save HSH for round {}", round).as_str());
+    }
+
+    let hands_preflop = enumerate_hands(game_def, 0);
+    let num_clusters = labels_preflop.iter().max().map(|&label| label as usize + 1).unwrap_or(0);
+    let mut clusters: Vec<Vec<Vec<u8>>> = vec![vec![]; num_clusters];
+    for (hand, &label) in hands_preflop.into_iter().zip(labels_preflop) {
+        clusters[label as usize].push(hand);
+    }
+
+    let last_round = game_def.rounds() - 1;
+    let opponent_cluster_hand_strengths = enumerate_hands(game_def, last_round).iter()
+        .map(|hand| opponent_cluster_hand_strengths(hand, &clusters, game_def))
+        .collect_vec();
+    log::info!("Finished toy game opponent cluster hand strengths. Round {} hands {}", last_round, opponent_cluster_hand_strengths.len());
+
+    save_opponent_cluster_hand_strengths_to_file(opponent_cluster_hand_strengths, last_round, 0, &export_path_opponent_cluster_hand_strengths)
+        .expect(format!("ERROR: Failed to save OCHS for round {}", last_round).as_str());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toy_games_have_every_hand_of_a_round() {
+        assert_eq!(enumerate_hands(GameDef::kuhn(), 0).len(), 3);
+        // 6 private cards, then 5 board cards for each
+        assert_eq!(enumerate_hands(GameDef::leduc(), 0).len(), 6);
+        assert_eq!(enumerate_hands(GameDef::leduc(), 1).len(), 30);
+    }
+
+    #[test]
+    fn kuhn_king_beats_queen_beats_jack() {
+        let hand_strengths = [44, 40, 36].map(|card| exact_hand_strengths(&[card], GameDef::kuhn()));
+        assert_eq!(hand_strengths, [vec![1.0], vec![0.5], vec![0.0]]);
+    }
+
+    #[test]
+    fn toy_game_histograms_sum_to_100() {
+        let bin_edges = uniform_bin_edges(10);
+        for game_def in [GameDef::kuhn(), GameDef::leduc()] {
+            for round in 0..game_def.rounds() {
+                for hand in enumerate_hands(game_def, round) {
+                    let histogram = hand_strength_histogram(&exact_hand_strengths(&hand, game_def), &bin_edges);
+                    assert_eq!(histogram.iter().map(|&bin_value| bin_value as u32).sum::<u32>(), 100, "Hand {:?}", hand);
+                }
+            }
+        }
+    }
+}
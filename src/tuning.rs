@@ -0,0 +1,117 @@
+use std::fs;
+use std::time::Instant;
+use ocl::enums::{KernelWorkGroupInfo, KernelWorkGroupInfoResult};
+use ocl::Kernel;
+use serde_json::{json, Map, Value};
+
+use crate::kernel::KernelContainer;
+
+static TUNING_CACHE_PATH: &str = "./tuning_cache.json";
+const TIMED_RUNS: usize = 5;
+
+// Hands per GPU chunk and the work-group size the kernel is enqueued with. Without a local size
+// the driver picks one
+#[derive(Clone, Copy, Debug)]
+pub struct LaunchSettings {
+    pub chunk_size: usize,
+    pub local_size: Option<usize>,
+}
+
+impl LaunchSettings {
+    // Global work size for a chunk of `num_hands` hands, rounded up to a multiple of the local size
+    pub fn global_work_size(&self, num_hands: usize) -> usize {
+        match self.local_size {
+            Some(local_size) => num_hands.div_ceil(local_size) * local_size,
+            None => num_hands,
+        }
+    }
+}
+
+// Launch settings of a kernel on a device, controlled by `OPENCL_TUNING`:
+// - unset: the cached settings, or `max_wg_size * 32` hands per chunk if there are none
+// - `auto`: the cached settings, benchmarked and cached first if there are none
+// - `retune`: benchmarked and cached again
+// `build` sets up the kernel and a chunk of hands for a chunk size, `kernel` gets the OpenCL kernel
+// out of it and `run` runs the chunk with the given settings. Only `run` is timed: after a warm-up
+// run, settings are scored by the median of `TIMED_RUNS` runs. The cache is keyed by device name
+// and `kernel_key`, which should include everything that changes the kernel's speed (like build
+// options)
+pub fn launch_settings<K>(
+    kernel_container: &KernelContainer,
+    kernel_key: &str,
+    mut build: impl FnMut(usize) -> K,
+    kernel: impl Fn(&K) -> &Kernel,
+    mut run: impl FnMut(&K, LaunchSettings)
+) -> LaunchSettings {
+    let max_work_group_size = kernel_container.device.max_wg_size().unwrap();
+    let cache_key = format!("{} | {}", kernel_container.device.name().unwrap_or_default(), kernel_key);
+    let mode = std::env::var("OPENCL_TUNING").unwrap_or_default();
+    let mut cache = load_tuning_cache();
+
+    if mode != "retune" {
+        if let Some(settings) = cache.get(&cache_key).and_then(parse_launch_settings) {
+            log::info!("Using cached launch settings {:?} for {}", settings, cache_key);
+            return settings;
+        }
+        if mode != "auto" {
+            return LaunchSettings { chunk_size: max_work_group_size * 32, local_size: None };
+        }
+    }
+
+    let chunk_sizes = [8, 16, 32, 64].map(|multiplier| max_work_group_size * multiplier);
+    let mut benchmarks = Vec::new();
+    for chunk_size in chunk_sizes {
+        let benchmark_kernel = build(chunk_size);
+        // The kernel's own limit can be below the device's, depending on its registers and local memory
+        let kernel_work_group_size = match kernel(&benchmark_kernel).wg_info(kernel_container.device, KernelWorkGroupInfo::WorkGroupSize) {
+            Ok(KernelWorkGroupInfoResult::WorkGroupSize(size)) => size,
+            _ => max_work_group_size,
+        };
+        let local_sizes = [32, 64, 128, 256, 512, 1024].into_iter()
+            .filter(|&local_size| local_size <= kernel_work_group_size)
+            .map(Some)
+            .chain([None]);
+
+        for local_size in local_sizes {
+            let settings = LaunchSettings { chunk_size, local_size };
+            run(&benchmark_kernel, settings);
+            let mut run_seconds = (0..TIMED_RUNS)
+                .map(|_| {
+                    let start = Instant::now();
+                    run(&benchmark_kernel, settings);
+                    start.elapsed().as_secs_f64()
+                })
+                .collect::<Vec<_>>();
+            run_seconds.sort_by(f64::total_cmp);
+            let hands_per_second = chunk_size as f64 / run_seconds[TIMED_RUNS / 2];
+            log::info!("Launch settings {:?} ran {:.0} hands/s", settings, hands_per_second);
+            benchmarks.push((settings, hands_per_second));
+        }
+    }
+
+    // Fastest by hands per second
+    let (best_settings, _) = benchmarks.into_iter()
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .unwrap();
+
+    log::info!("Tuned launch settings {:?} for {}", best_settings, cache_key);
+    cache.insert(cache_key, json!({ "chunk_size": best_settings.chunk_size, "local_size": best_settings.local_size }));
+    if let Err(error) = fs::write(TUNING_CACHE_PATH, Value::Object(cache).to_string()) {
+        log::error!("Failed to write tuning cache {}: {}", TUNING_CACHE_PATH, error);
+    }
+    best_settings
+}
+
+fn load_tuning_cache() -> Map<String, Value> {
+    fs::read_to_string(TUNING_CACHE_PATH).ok()
+        .and_then(|contents| serde_json::from_str::<Value>(&contents).ok())
+        .and_then(|value| value.as_object().cloned())
+        .unwrap_or_default()
+}
+
+fn parse_launch_settings(value: &Value) -> Option<LaunchSettings> {
+    Some(LaunchSettings {
+        chunk_size: value.get("chunk_size")?.as_u64()? as usize,
+        local_size: value.get("local_size")?.as_u64().map(|local_size| local_size as usize),
+    })
+}
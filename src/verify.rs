@@ -0,0 +1,172 @@
+use itertools::Itertools;
+
+use crate::hand_source::HandSource;
+use crate::hand_strength_histogram::load::load_hand_strength_histograms_with_metadata_from_file;
+use crate::opponent_cluster_hand_strength::load::load_opponent_cluster_hand_strengths_from_file;
+
+// Bad rows logged per batch, the rest are only counted
+const MAX_LOGGED_ROWS: usize = 20;
+
+pub struct BadRow {
+    pub batch: usize,
+    pub row: usize,
+    pub problem: String,
+}
+
+pub struct VerifyReport {
+    pub rows_checked: usize,
+    pub bad_rows: Vec<BadRow>,
+}
+
+// Histogram bins are percentages rounded down, so a histogram sums to at most 100 and loses less
+// than one per bin
+pub fn hand_strength_histogram_row_problem(row: &[u8], num_bins: usize) -> Option<String> {
+    if row.len() != num_bins {
+        return Some(format!("has {} bins instead of {}", row.len(), num_bins));
+    }
+    let sum = row.iter().map(|&bin_value| bin_value as usize).sum::<usize>();
+    if sum > 100 || sum + num_bins <= 100 {
+        return Some(format!("bins sum to {}", sum));
+    }
+    None
+}
+
+// Opponent cluster hand strengths are win rates scaled to 0-255, one per cluster
+pub fn opponent_cluster_hand_strength_row_problem(row: &[i32]) -> Option<String> {
+    if row.len() != 8 {
+        return Some(format!("has {} values instead of 8", row.len()));
+    }
+    if let Some(value) = row.iter().find(|&&value| !(0..=255).contains(&value)) {
+        return Some(format!("has value {} outside 0-255", value));
+    }
+    None
+}
+
+pub fn find_bad_rows<T>(batch: usize, rows: &[T], expected_rows: usize, row_problem: impl Fn(&T) -> Option<String>) -> Vec<BadRow> {
+    let mut bad_rows = rows.iter()
+        .enumerate()
+        .filter_map(|(row, values)| row_problem(values).map(|problem| BadRow { batch, row, problem }))
+        .collect_vec();
+    // A missing or extra row shifts every row after it, so it's reported on the first row that
+    // has no hand or no histogram
+    if rows.len() != expected_rows {
+        bad_rows.push(BadRow {
+            batch,
+            row: rows.len().min(expected_rows),
+            problem: format!("batch has {} rows for {} hands", rows.len(), expected_rows),
+        });
+    }
+    bad_rows
+}
+
+pub fn log_bad_rows(feature: &str, round: usize, bad_rows: &[BadRow]) {
+    for bad_row in bad_rows.iter().take(MAX_LOGGED_ROWS) {
+        log::error!("Bad {} row. Round {}, batch {} row {} {}", feature, round, bad_row.batch, bad_row.row, bad_row.problem);
+    }
+    if bad_rows.len() > MAX_LOGGED_ROWS {
+        log::error!("{} more bad {} rows in round {}", bad_rows.len() - MAX_LOGGED_ROWS, feature, round);
+    }
+}
+
+// Checks every exported histogram of a round against the batches of `hand_source`
+pub fn verify_hand_strength_histograms(hand_source: &mut dyn HandSource, import_path: &str) -> VerifyReport {
+    verify_batches(hand_source, "HSH", |round, batch_index, hands| {
+        let hand_strength_histograms = load_hand_strength_histograms_with_metadata_from_file(round, batch_index, import_path)
+            .expect(format!("ERROR: Failed to load HSH for round {} batch #{}", round, batch_index).as_str());
+        let num_bins = hand_strength_histograms.bin_edges.len().saturating_sub(1);
+        let trials = &hand_strength_histograms.trials;
+
+        let mut bad_rows = find_bad_rows(batch_index, &hand_strength_histograms.data, hands.len(), |row| {
+            hand_strength_histogram_row_problem(row, num_bins)
+        });
+        if !trials.is_empty() && trials.len() != hand_strength_histograms.data.len() {
+            bad_rows.push(BadRow {
+                batch: batch_index,
+                row: trials.len().min(hand_strength_histograms.data.len()),
+                problem: format!("batch has {} trial counts for {} rows", trials.len(), hand_strength_histograms.data.len()),
+            });
+        }
+        bad_rows.extend(trials.iter()
+            .positions(|&hand_trials| hand_trials == 0)
+            .map(|row| BadRow { batch: batch_index, row, problem: "was sampled from 0 trials".to_string() }));
+        (hand_strength_histograms.data.len(), bad_rows)
+    })
+}
+
+// Checks every exported OCHS row of a round against the batches of `hand_source`. The files store
+// the values as bytes, so they're always in 0-255 and only the row lengths and the number of rows
+// can be wrong. The value range is checked on the kernel output, before it's saved
+pub fn verify_opponent_cluster_hand_strengths(hand_source: &mut dyn HandSource, import_path: &str) -> VerifyReport {
+    verify_batches(hand_source, "OCHS", |round, batch_index, hands| {
+        let rows = load_opponent_cluster_hand_strengths_from_file(round, batch_index, import_path)
+            .expect(format!("ERROR: Failed to load OCHS for round {} batch #{}", round, batch_index).as_str());
+
+        let bad_rows = find_bad_rows(batch_index, &rows, hands.len(), |row| {
+            if row.len() != 8 {
+                return Some(format!("has {} values instead of 8", row.len()));
+            }
+            None
+        });
+        (rows.len(), bad_rows)
+    })
+}
+
+fn verify_batches(
+    hand_source: &mut dyn HandSource,
+    feature: &str,
+    mut verify_batch: impl FnMut(usize, usize, &[Vec<u8>]) -> (usize, Vec<BadRow>)
+) -> VerifyReport {
+    let round = hand_source.round();
+    let total_batches = hand_source.total_batches();
+    log::info!("Verifying {} of round {} in {} batches", feature, round, total_batches);
+
+    let mut report = VerifyReport { rows_checked: 0, bad_rows: vec![] };
+    for batch_index in 0..total_batches {
+        let (rows_checked, bad_rows) = verify_batch(round, batch_index, hand_source.current_batch_hands());
+        log::info!(
+            "Verified batch. Round {}, batch {}/{} rows {} bad rows {}",
+            round,
+            batch_index,
+            total_batches-1,
+            rows_checked,
+            bad_rows.len()
+        );
+        report.rows_checked += rows_checked;
+        report.bad_rows.extend(bad_rows);
+        hand_source.load_next_batch();
+    }
+
+    log_bad_rows(feature, round, &report.bad_rows);
+    log::info!("Verified {} {} rows of round {}, {} bad", report.rows_checked, feature, round, report.bad_rows.len());
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hand_strength_histogram_rows_need_every_bin_and_a_sum_near_100() {
+        assert!(hand_strength_histogram_row_problem(&[25, 25, 25, 25], 4).is_none());
+        // Rounding down loses less than one per bin
+        assert!(hand_strength_histogram_row_problem(&[33, 33, 33], 3).is_none());
+        assert!(hand_strength_histogram_row_problem(&[50, 50], 4).is_some());
+        assert!(hand_strength_histogram_row_problem(&[50, 51, 0, 0], 4).is_some());
+        assert!(hand_strength_histogram_row_problem(&[24, 24, 24, 24], 4).is_some());
+    }
+
+    #[test]
+    fn missing_and_extra_rows_are_reported_after_the_last_matching_row() {
+        let rows = vec![vec![100u8, 0], vec![50, 50], vec![0, 10]];
+        let row_problem = |row: &Vec<u8>| hand_strength_histogram_row_problem(row, 2);
+
+        let bad_rows = find_bad_rows(7, &rows, 3, row_problem);
+        assert_eq!(bad_rows.iter().map(|bad_row| (bad_row.batch, bad_row.row)).collect_vec(), vec![(7, 2)]);
+
+        let missing = find_bad_rows(7, &rows[..2], 3, row_problem);
+        assert_eq!(missing.iter().map(|bad_row| bad_row.row).collect_vec(), vec![2]);
+
+        let extra = find_bad_rows(7, &rows[..2], 1, row_problem);
+        assert_eq!(extra.iter().map(|bad_row| bad_row.row).collect_vec(), vec![1]);
+    }
+}